@@ -65,8 +65,13 @@ extern crate log;
 
 mod ssh;
 pub use ssh::{
-    KeyMethod, MethodType, ParseRule as SshConfigParseRule, ScpFs, SftpFs, SshAgentIdentity,
-    SshKeyStorage, SshOpts,
+    is_not_retried, is_timeout, AddressFamily, AlgorithmProfile, AlwaysAcceptVerifier, AuthMethod,
+    AuthPrompt, ConnectionInfo, HostKey, HostKeyCheck, HostKeyType, HostKeyVerdict,
+    HostKeyVerifier, KeyMethod, KeyboardInteractiveHandler, MethodType, NegotiatedAlgorithms,
+    OptionSource, ParseRule as SshConfigParseRule, PassphraseProvider, PasswordProvider,
+    PinnedFingerprintVerifier, Proxy, ProxyProtocol, Resolved, ResolvedAlgorithms, ResolvedConfig,
+    RetryPhase, RetryPolicy, ScpFs, Secret, SessionStream, SftpFs, SharedSession, SshAgentIdentity,
+    SshConnector, SshKey, SshKeyMaterial, SshKeyStorage, SshOpts, NOT_RETRIED, TIMED_OUT,
 };
 
 // -- utils
@@ -0,0 +1,337 @@
+//! ## Algorithms
+//!
+//! algorithm profiles and validation of the preferred algorithms
+
+use std::fmt;
+use std::str::FromStr;
+
+use remotefs::{RemoteError, RemoteErrorType, RemoteResult};
+use ssh2::Session;
+
+use super::MethodType;
+
+// -- profiles
+
+const MODERN_KEX: &[&str] = &[
+    "curve25519-sha256",
+    "curve25519-sha256@libssh.org",
+    "ecdh-sha2-nistp521",
+    "ecdh-sha2-nistp384",
+    "ecdh-sha2-nistp256",
+    "diffie-hellman-group18-sha512",
+    "diffie-hellman-group16-sha512",
+];
+const MODERN_HOST_KEY: &[&str] = &[
+    "ssh-ed25519",
+    "ecdsa-sha2-nistp521",
+    "ecdsa-sha2-nistp384",
+    "ecdsa-sha2-nistp256",
+    "rsa-sha2-512",
+    "rsa-sha2-256",
+];
+const MODERN_CIPHERS: &[&str] = &[
+    "chacha20-poly1305@openssh.com",
+    "aes256-gcm@openssh.com",
+    "aes128-gcm@openssh.com",
+    "aes256-ctr",
+    "aes192-ctr",
+    "aes128-ctr",
+];
+const MODERN_MAC: &[&str] = &[
+    "hmac-sha2-512-etm@openssh.com",
+    "hmac-sha2-256-etm@openssh.com",
+    "hmac-sha2-512",
+    "hmac-sha2-256",
+];
+
+const COMPATIBLE_KEX: &[&str] = &[
+    "curve25519-sha256",
+    "curve25519-sha256@libssh.org",
+    "ecdh-sha2-nistp521",
+    "ecdh-sha2-nistp384",
+    "ecdh-sha2-nistp256",
+    "diffie-hellman-group-exchange-sha256",
+    "diffie-hellman-group18-sha512",
+    "diffie-hellman-group16-sha512",
+    "diffie-hellman-group14-sha256",
+    "diffie-hellman-group14-sha1",
+];
+const COMPATIBLE_HOST_KEY: &[&str] = &[
+    "ssh-ed25519",
+    "ecdsa-sha2-nistp521",
+    "ecdsa-sha2-nistp384",
+    "ecdsa-sha2-nistp256",
+    "rsa-sha2-512",
+    "rsa-sha2-256",
+    "ssh-rsa",
+];
+const COMPATIBLE_MAC: &[&str] = &[
+    "hmac-sha2-512-etm@openssh.com",
+    "hmac-sha2-256-etm@openssh.com",
+    "hmac-sha2-512",
+    "hmac-sha2-256",
+    "hmac-sha1",
+];
+
+const LEGACY_KEX: &[&str] = &[
+    "curve25519-sha256",
+    "curve25519-sha256@libssh.org",
+    "ecdh-sha2-nistp521",
+    "ecdh-sha2-nistp384",
+    "ecdh-sha2-nistp256",
+    "diffie-hellman-group-exchange-sha256",
+    "diffie-hellman-group18-sha512",
+    "diffie-hellman-group16-sha512",
+    "diffie-hellman-group14-sha256",
+    "diffie-hellman-group14-sha1",
+    "diffie-hellman-group-exchange-sha1",
+    "diffie-hellman-group1-sha1",
+];
+const LEGACY_HOST_KEY: &[&str] = &[
+    "ssh-ed25519",
+    "ecdsa-sha2-nistp521",
+    "ecdsa-sha2-nistp384",
+    "ecdsa-sha2-nistp256",
+    "rsa-sha2-512",
+    "rsa-sha2-256",
+    "ssh-rsa",
+    "ssh-dss",
+];
+const LEGACY_CIPHERS: &[&str] = &[
+    "chacha20-poly1305@openssh.com",
+    "aes256-gcm@openssh.com",
+    "aes128-gcm@openssh.com",
+    "aes256-ctr",
+    "aes192-ctr",
+    "aes128-ctr",
+    "aes256-cbc",
+    "aes192-cbc",
+    "aes128-cbc",
+    "3des-cbc",
+];
+const LEGACY_MAC: &[&str] = &[
+    "hmac-sha2-512-etm@openssh.com",
+    "hmac-sha2-256-etm@openssh.com",
+    "hmac-sha2-512",
+    "hmac-sha2-256",
+    "hmac-sha1",
+    "hmac-sha1-96",
+    "hmac-md5",
+];
+
+/// Named set of preferred key exchange, host key, cipher and MAC algorithms.
+///
+/// Algorithms which are not supported by the libssh2 build are left out
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum AlgorithmProfile {
+    /// Only algorithms currently considered secure: curve25519 and ECDH key exchange,
+    /// ed25519, ECDSA and RSA SHA-2 host keys, AEAD and CTR ciphers, SHA-2 MACs
+    Modern,
+    /// `Modern`, plus the algorithms needed by older servers still in use:
+    /// group exchange and group14 key exchange, `ssh-rsa` host keys, `hmac-sha1`
+    Compatible,
+    /// `Compatible`, plus deprecated algorithms, for legacy devices only:
+    /// group1 key exchange, `ssh-dss` host keys, CBC ciphers, MD5 MACs
+    Legacy,
+}
+
+impl AlgorithmProfile {
+    /// Get the preferred algorithms of the profile for `method_type`
+    pub fn algorithms(&self, method_type: MethodType) -> &'static [&'static str] {
+        match (self, method_type) {
+            (Self::Modern, MethodType::Kex) => MODERN_KEX,
+            (Self::Modern, MethodType::HostKey) => MODERN_HOST_KEY,
+            (
+                Self::Modern | Self::Compatible,
+                MethodType::CryptClientServer | MethodType::CryptServerClient,
+            ) => MODERN_CIPHERS,
+            (Self::Modern, MethodType::MacClientServer | MethodType::MacServerClient) => MODERN_MAC,
+            (Self::Compatible, MethodType::Kex) => COMPATIBLE_KEX,
+            (Self::Compatible, MethodType::HostKey) => COMPATIBLE_HOST_KEY,
+            (Self::Compatible, MethodType::MacClientServer | MethodType::MacServerClient) => {
+                COMPATIBLE_MAC
+            }
+            (Self::Legacy, MethodType::Kex) => LEGACY_KEX,
+            (Self::Legacy, MethodType::HostKey) => LEGACY_HOST_KEY,
+            (Self::Legacy, MethodType::CryptClientServer | MethodType::CryptServerClient) => {
+                LEGACY_CIPHERS
+            }
+            (Self::Legacy, MethodType::MacClientServer | MethodType::MacServerClient) => LEGACY_MAC,
+        }
+    }
+}
+
+impl fmt::Display for AlgorithmProfile {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Modern => write!(f, "modern"),
+            Self::Compatible => write!(f, "compatible"),
+            Self::Legacy => write!(f, "legacy"),
+        }
+    }
+}
+
+impl FromStr for AlgorithmProfile {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_ascii_lowercase().as_str() {
+            "modern" => Ok(Self::Modern),
+            "compatible" => Ok(Self::Compatible),
+            "legacy" => Ok(Self::Legacy),
+            _ => Err(format!("unknown algorithm profile '{s}'")),
+        }
+    }
+}
+
+// -- validation
+
+/// Check `algos` preferred for `method_type` against the algorithms supported by the libssh2 build,
+/// returning them in ssh protocol syntax.
+///
+/// If `strict`, any unsupported algorithm is an error; otherwise unsupported algorithms are left out,
+/// and it is an error only if none is supported
+pub(crate) fn supported_prefs(
+    session: &Session,
+    method_type: MethodType,
+    algos: &[String],
+    strict: bool,
+) -> RemoteResult<String> {
+    let supported = session.supported_algs(method_type.into()).map_err(|err| {
+        error!(
+            "Could not get supported {} algorithms: {err}",
+            method_name(method_type)
+        );
+        RemoteError::new_ex(RemoteErrorType::ProtocolError, err)
+    })?;
+    let (known, unknown): (Vec<&str>, Vec<&str>) = algos
+        .iter()
+        .map(String::as_str)
+        .partition(|x| supported.contains(x));
+    if known.is_empty() || (strict && !unknown.is_empty()) {
+        error!(
+            "Unsupported {} algorithms: {}",
+            method_name(method_type),
+            unknown.join(",")
+        );
+        return Err(RemoteError::new_ex(
+            RemoteErrorType::UnsupportedFeature,
+            format!(
+                "unsupported {} algorithms '{}'; supported algorithms are: {}",
+                method_name(method_type),
+                unknown.join(","),
+                supported.join(",")
+            ),
+        ));
+    }
+    if !unknown.is_empty() {
+        debug!(
+            "Skipping unsupported {} algorithms: {}",
+            method_name(method_type),
+            unknown.join(",")
+        );
+    }
+    Ok(known.join(","))
+}
+
+/// Name of `method_type` for messages
+fn method_name(method_type: MethodType) -> &'static str {
+    match method_type {
+        MethodType::CryptClientServer => "cipher (client to server)",
+        MethodType::CryptServerClient => "cipher (server to client)",
+        MethodType::HostKey => "host key",
+        MethodType::Kex => "key exchange",
+        MethodType::MacClientServer => "MAC (client to server)",
+        MethodType::MacServerClient => "MAC (server to client)",
+    }
+}
+
+#[cfg(test)]
+mod test {
+
+    use pretty_assertions::assert_eq;
+
+    use super::*;
+
+    const METHOD_TYPES: [MethodType; 6] = [
+        MethodType::Kex,
+        MethodType::HostKey,
+        MethodType::CryptClientServer,
+        MethodType::CryptServerClient,
+        MethodType::MacClientServer,
+        MethodType::MacServerClient,
+    ];
+
+    #[test]
+    fn should_parse_algorithm_profile() {
+        for profile in [
+            AlgorithmProfile::Modern,
+            AlgorithmProfile::Compatible,
+            AlgorithmProfile::Legacy,
+        ] {
+            assert_eq!(
+                AlgorithmProfile::from_str(&profile.to_string()).unwrap(),
+                profile
+            );
+        }
+        assert_eq!(
+            AlgorithmProfile::from_str("Modern").unwrap(),
+            AlgorithmProfile::Modern
+        );
+        assert!(AlgorithmProfile::from_str("paranoid").is_err());
+    }
+
+    #[test]
+    fn should_expand_profiles_to_supported_algorithms() {
+        let session = Session::new().unwrap();
+        for profile in [
+            AlgorithmProfile::Modern,
+            AlgorithmProfile::Compatible,
+            AlgorithmProfile::Legacy,
+        ] {
+            for method_type in METHOD_TYPES {
+                let algos: Vec<String> = profile
+                    .algorithms(method_type)
+                    .iter()
+                    .map(|x| x.to_string())
+                    .collect();
+                assert!(supported_prefs(&session, method_type, &algos, false).is_ok());
+            }
+        }
+        // each profile extends the previous one
+        for method_type in METHOD_TYPES {
+            let compatible = AlgorithmProfile::Compatible.algorithms(method_type);
+            assert!(AlgorithmProfile::Modern
+                .algorithms(method_type)
+                .iter()
+                .all(|x| compatible.contains(x)));
+            let legacy = AlgorithmProfile::Legacy.algorithms(method_type);
+            assert!(compatible.iter().all(|x| legacy.contains(x)));
+        }
+        assert!(!AlgorithmProfile::Modern
+            .algorithms(MethodType::HostKey)
+            .contains(&"ssh-rsa"));
+    }
+
+    #[test]
+    fn should_validate_algorithms() {
+        let session = Session::new().unwrap();
+        let algos = vec!["aes256-ctr".to_string(), "aes512-ctr".to_string()];
+        assert_eq!(
+            supported_prefs(&session, MethodType::CryptClientServer, &algos, false).unwrap(),
+            "aes256-ctr"
+        );
+        let err =
+            supported_prefs(&session, MethodType::CryptClientServer, &algos, true).unwrap_err();
+        assert_eq!(err.kind, RemoteErrorType::UnsupportedFeature);
+        assert!(err.msg.as_deref().unwrap().contains("'aes512-ctr'"));
+        assert!(err.msg.as_deref().unwrap().contains("aes128-ctr"));
+        // none supported
+        assert_eq!(
+            supported_prefs(&session, MethodType::Kex, &["foo".to_string()], false)
+                .unwrap_err()
+                .kind,
+            RemoteErrorType::UnsupportedFeature
+        );
+    }
+}
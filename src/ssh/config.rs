@@ -2,43 +2,158 @@
 //!
 //! implements configuration resolver for ssh
 
+use std::collections::HashMap;
 use std::fs::File;
-use std::io::BufReader;
-use std::path::Path;
+use std::io::{BufRead, BufReader};
+use std::net::IpAddr;
+use std::path::{Path, PathBuf};
+use std::str::FromStr;
 use std::time::Duration;
 
 use remotefs::{RemoteError, RemoteErrorType, RemoteResult};
-use ssh2_config::{HostParams, ParseRule, SshConfig};
+use ssh2_config::{Host, HostClause, HostParams, ParseRule, SshConfig};
 
-use super::SshOpts;
+use super::{AddressFamily, HostKeyCheck, SshOpts};
+
+/// Ssh configuration directives which are not exposed by `HostParams`.
+/// Keys are lowercase directive names; for each directive only the first obtained value is kept.
+pub type Directives = HashMap<String, Vec<String>>;
+
+/// Directives whose value is a command line, kept as written (quotes, spacing and `#` included) in a single argument
+const COMMAND_DIRECTIVES: &[&str] = &["proxycommand"];
+
+/// Characters which must not appear in the host and user names substituted in command lines, as in OpenSSH
+const SHELL_METACHARACTERS: &str = "'`\"$\\;&<>|(){}^";
+
+/// A jump host from `ProxyJump`: `[user@]host[:port]`
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct JumpHost {
+    pub host: String,
+    pub port: Option<u16>,
+    pub username: Option<String>,
+}
+
+/// Ssh agent to authenticate with, from `IdentityAgent`
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum IdentityAgent {
+    /// Agent at `SSH_AUTH_SOCK`
+    Default,
+    /// Agent listening on the socket
+    Socket(PathBuf),
+    /// Agent disabled
+    Disabled,
+}
+
+impl FromStr for JumpHost {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let s = s.strip_prefix("ssh://").unwrap_or(s);
+        let (username, address) = match s.rsplit_once('@') {
+            Some((username, address)) => (Some(username.to_string()), address),
+            None => (None, s),
+        };
+        // IPv6 addresses must be enclosed in brackets to specify the port
+        let (host, port) = match address.strip_prefix('[') {
+            Some(address) => match address.split_once(']') {
+                Some((host, "")) => (host, None),
+                Some((host, port)) => (host, port.strip_prefix(':').map(|x| x.to_string())),
+                None => return Err(format!("invalid jump host '{s}'")),
+            },
+            None => match address.split_once(':') {
+                Some((host, port)) => (host, Some(port.to_string())),
+                None => (address, None),
+            },
+        };
+        if host.is_empty() {
+            return Err(format!("invalid jump host '{s}'"));
+        }
+        let port = port
+            .map(|x| u16::from_str(&x).map_err(|_| format!("invalid port in jump host '{s}'")))
+            .transpose()?;
+        Ok(Self {
+            host: host.to_string(),
+            port,
+            username,
+        })
+    }
+}
 
 /// Ssh configuration params
 pub struct Config {
     pub params: HostParams,
+    /// Directives of the configuration file not exposed by `params`
+    pub directives: Directives,
     pub host: String,
-    /// Host resolved from configuration
+    /// Host resolved from configuration; IPv6 addresses are not enclosed in brackets
     pub resolved_host: String,
-    /// Address is host:port
+    /// Address is host:port, or [host]:port for IPv6 addresses
     pub address: String,
+    pub port: u16,
     pub username: String,
     pub connection_timeout: Duration,
     pub connection_attempts: usize,
+    /// Host key checking policy
+    pub host_key_check: HostKeyCheck,
+    /// Known hosts files; new keys are written to the first one
+    pub known_hosts_files: Vec<PathBuf>,
+    /// Private key files to authenticate with, from `IdentityFile` or the default keys
+    pub identity_files: Vec<PathBuf>,
+    /// Whether only the configured identities should be used, even if the ssh agent offers more
+    pub identities_only: bool,
+    /// Ssh agent to authenticate with
+    pub identity_agent: IdentityAgent,
+    /// User certificate from `CertificateFile`
+    pub certificate_file: Option<PathBuf>,
+    /// Jump hosts from `ProxyJump`, in connection order
+    pub proxy_jump: Vec<JumpHost>,
+    /// Command to connect to the server through, with its tokens expanded
+    pub proxy_command: Option<String>,
+    /// Interval to send keepalive messages at; `None` if disabled
+    pub server_alive_interval: Option<Duration>,
+    /// Keepalive messages which may fail in a row before the connection is considered dead
+    pub server_alive_count_max: u32,
+    /// Address family of the addresses to connect with
+    pub address_family: AddressFamily,
+    /// Local address to connect from
+    pub bind_address: Option<IpAddr>,
 }
 
 impl Config {
     // -- private
 
-    /// Create `Config` from `HostParams` and `SshOpts`
-    fn from_params(params: HostParams, opts: &SshOpts) -> Self {
-        Config {
+    /// Create `Config` from `HostParams`, `Directives` and `SshOpts`
+    fn from_params(
+        params: HostParams,
+        directives: Directives,
+        opts: &SshOpts,
+    ) -> RemoteResult<Self> {
+        if Self::requests_forward_agent(&directives) {
+            warn!("ForwardAgent is not supported: libssh2 can't serve the agent channels opened by the server; the agent is not forwarded");
+        }
+        Ok(Config {
             host: opts.host.to_string(),
             resolved_host: Self::resolve_host(&params, opts),
             address: Self::resolve_address(&params, opts),
+            port: Self::resolve_port(&params, opts),
             username: Self::resolve_username(&params, opts),
             connection_timeout: Self::resolve_connection_timeout(&params, opts),
-            connection_attempts: Self::resolve_connection_attempts(&params),
+            connection_attempts: Self::resolve_connection_attempts(&params, opts),
+            host_key_check: Self::resolve_host_key_check(&directives, opts),
+            known_hosts_files: Self::resolve_known_hosts_files(&directives, opts),
+            identity_files: Self::resolve_identity_files(&params, opts),
+            identities_only: Self::resolve_identities_only(&directives),
+            identity_agent: Self::resolve_identity_agent(&directives, &params, opts),
+            certificate_file: Self::resolve_certificate_file(&params, opts),
+            proxy_jump: Self::resolve_proxy_jump(&directives, opts)?,
+            proxy_command: Self::resolve_proxy_command(&directives, &params, opts)?,
+            server_alive_interval: Self::resolve_server_alive_interval(&params, opts),
+            server_alive_count_max: Self::resolve_server_alive_count_max(&directives, opts),
+            address_family: Self::resolve_address_family(&directives, opts),
+            bind_address: Self::resolve_bind_address(&params, opts)?,
             params,
-        }
+            directives,
+        })
     }
 
     /// Parse config at `p` and get params for `host`
@@ -61,24 +176,113 @@ impl Config {
             .map(|x| x.query(host))
     }
 
-    /// Given host params and ssh options, returns resolved remote host
+    /// Read the directives at `p` which are not supported by the ssh config parser for `host`.
+    ///
+    /// Host blocks are matched with the same rules of the parser; `Match` blocks are not supported and skipped.
+    fn parse_directives(p: &Path, host: &str) -> RemoteResult<Directives> {
+        trace!("Reading unsupported directives at {}", p.display());
+        let reader = BufReader::new(File::open(p).map_err(|e| {
+            RemoteError::new_ex(
+                RemoteErrorType::IoError,
+                format!("Could not open configuration file: {e}"),
+            )
+        })?);
+        let mut directives = Directives::new();
+        // Options preceding the first `Host` section apply to every host
+        let mut matches = true;
+        for line in reader.lines() {
+            let raw_line = line.map_err(|e| {
+                RemoteError::new_ex(
+                    RemoteErrorType::IoError,
+                    format!("Could not read configuration file: {e}"),
+                )
+            })?;
+            let raw_line = raw_line.trim();
+            let line = match raw_line.find('#') {
+                Some(pos) => &raw_line[..pos],
+                None => raw_line,
+            };
+            let line = line.trim();
+            if line.is_empty() {
+                continue;
+            }
+            // Directives may be written as `Key Value` or `Key=Value`
+            let (field, args) = match line.find(|c: char| c.is_whitespace() || c == '=') {
+                Some(pos) => (
+                    &line[..pos],
+                    line[pos..]
+                        .trim_start_matches(|c: char| c.is_whitespace() || c == '=')
+                        .split_whitespace()
+                        .map(|x| x.to_string())
+                        .collect::<Vec<String>>(),
+                ),
+                None => (line, Vec::new()),
+            };
+            match field.to_lowercase().as_str() {
+                "host" => {
+                    let clauses = args
+                        .iter()
+                        .map(|x| match x.strip_prefix('!') {
+                            Some(pattern) => HostClause::new(pattern.to_string(), true),
+                            None => HostClause::new(x.to_string(), false),
+                        })
+                        .collect();
+                    matches = Host::new(clauses, HostParams::default()).intersects(host);
+                }
+                "match" => {
+                    matches = false;
+                }
+                field if matches && COMMAND_DIRECTIVES.contains(&field) => {
+                    let command = raw_line[field.len()..]
+                        .trim_start_matches(|c: char| c.is_whitespace() || c == '=');
+                    if !command.is_empty() {
+                        directives
+                            .entry(field.to_string())
+                            .or_insert_with(|| vec![command.to_string()]);
+                    }
+                }
+                field if matches && !args.is_empty() => {
+                    directives.entry(field.to_string()).or_insert(args);
+                }
+                _ => {}
+            }
+        }
+        Ok(directives)
+    }
+
+    /// Given host params and ssh options, returns resolved remote host.
+    /// Brackets enclosing IPv6 addresses (e.g. `[::1]`) are removed
     fn resolve_host(params: &HostParams, opts: &SshOpts) -> String {
         // Host should be overridden
-        match params.host_name.as_deref() {
-            Some(h) => h.to_string(),
-            None => opts.host.to_string(),
-        }
+        let host = match params.host_name.as_deref() {
+            Some(h) => h,
+            None => opts.host.as_str(),
+        };
+        host.strip_prefix('[')
+            .and_then(|x| x.strip_suffix(']'))
+            .unwrap_or(host)
+            .to_string()
     }
 
-    /// Given host params and ssh options, returns resolved remote address
+    /// Given host params and ssh options, returns resolved remote address.
+    /// IPv6 addresses, with their zone id if any (e.g. `fe80::1%eth0`), are enclosed in brackets
     fn resolve_address(params: &HostParams, opts: &SshOpts) -> String {
         let host = Self::resolve_host(params, opts);
+        let port = Self::resolve_port(params, opts);
+        if host.contains(':') {
+            format!("[{host}]:{port}")
+        } else {
+            format!("{host}:{port}")
+        }
+    }
+
+    /// Given host params and ssh options, returns remote port
+    fn resolve_port(params: &HostParams, opts: &SshOpts) -> u16 {
         // Opts.port has priority
-        let port = match opts.port {
+        match opts.port {
             None => params.port.unwrap_or(22),
             Some(p) => p,
-        };
-        format!("{host}:{port}")
+        }
     }
 
     /// Resolve username from opts and params.
@@ -102,10 +306,347 @@ impl Config {
         }
     }
 
-    /// Given host params, resolve connection attempts.
-    /// If `none`, gets 1
-    fn resolve_connection_attempts(params: &HostParams) -> usize {
-        params.connection_attempts.unwrap_or(1)
+    /// Resolve connection attempts from the retry policy and `ConnectionAttempts`.
+    /// If none is set, gets 1
+    fn resolve_connection_attempts(params: &HostParams, opts: &SshOpts) -> usize {
+        match opts.retry_policy.attempts() {
+            Some(attempts) => attempts as usize,
+            None => params.connection_attempts.unwrap_or(1),
+        }
+    }
+
+    /// Resolve keepalive interval from opts and `ServerAliveInterval`.
+    /// If none is set or the interval is zero, keepalives are disabled
+    fn resolve_server_alive_interval(params: &HostParams, opts: &SshOpts) -> Option<Duration> {
+        opts.server_alive_interval
+            .or(params.server_alive_interval)
+            .filter(|x| x.as_secs() > 0)
+    }
+
+    /// Resolve keepalive count max from opts and `ServerAliveCountMax`.
+    /// If none is set, gets 3
+    fn resolve_server_alive_count_max(directives: &Directives, opts: &SshOpts) -> u32 {
+        if let Some(count) = opts.server_alive_count_max {
+            return count.max(1);
+        }
+        match directives
+            .get("serveralivecountmax")
+            .and_then(|x| x.first())
+        {
+            Some(count) => match u32::from_str(count) {
+                Ok(count) => count.max(1),
+                Err(_) => {
+                    warn!("Invalid ServerAliveCountMax value '{count}'; using 3");
+                    3
+                }
+            },
+            None => 3,
+        }
+    }
+
+    /// Resolve address family from opts and `AddressFamily`.
+    /// If none is set, any address family is used
+    fn resolve_address_family(directives: &Directives, opts: &SshOpts) -> AddressFamily {
+        if let Some(family) = opts.address_family {
+            return family;
+        }
+        match directives
+            .get("addressfamily")
+            .and_then(|x| x.first())
+            .map(|x| x.to_lowercase())
+            .as_deref()
+        {
+            Some("inet") => AddressFamily::Inet,
+            Some("inet6") => AddressFamily::Inet6,
+            Some("any") | None => AddressFamily::Any,
+            Some(family) => {
+                warn!("Invalid AddressFamily value '{family}'; using any");
+                AddressFamily::Any
+            }
+        }
+    }
+
+    /// Resolve local address to connect from, from opts and `BindAddress`
+    fn resolve_bind_address(params: &HostParams, opts: &SshOpts) -> RemoteResult<Option<IpAddr>> {
+        if let Some(address) = opts.bind_address {
+            return Ok(Some(address));
+        }
+        params
+            .bind_address
+            .as_deref()
+            .map(|x| {
+                IpAddr::from_str(x).map_err(|_| {
+                    RemoteError::new_ex(
+                        RemoteErrorType::BadAddress,
+                        format!("invalid BindAddress '{x}'"),
+                    )
+                })
+            })
+            .transpose()
+    }
+
+    /// Resolve host key checking policy from opts and `StrictHostKeyChecking`.
+    /// If none is set, keys of unknown hosts are accepted and recorded
+    fn resolve_host_key_check(directives: &Directives, opts: &SshOpts) -> HostKeyCheck {
+        if let Some(check) = opts.host_key_check {
+            return check;
+        }
+        match directives
+            .get("stricthostkeychecking")
+            .and_then(|x| x.first())
+            .map(|x| x.to_lowercase())
+            .as_deref()
+        {
+            Some("yes") | Some("ask") => HostKeyCheck::Strict,
+            Some("accept-new") => HostKeyCheck::AcceptNew,
+            Some("no") | Some("off") => HostKeyCheck::Off,
+            Some(other) => {
+                warn!("Unknown StrictHostKeyChecking value '{other}'; using strict checking");
+                HostKeyCheck::Strict
+            }
+            None => HostKeyCheck::AcceptNew,
+        }
+    }
+
+    /// Resolve known hosts files from opts and `UserKnownHostsFile`.
+    /// If none is set, `~/.ssh/known_hosts` is used
+    fn resolve_known_hosts_files(directives: &Directives, opts: &SshOpts) -> Vec<PathBuf> {
+        if let Some(p) = opts.known_hosts_file.as_deref() {
+            return vec![p.to_path_buf()];
+        }
+        match directives.get("userknownhostsfile") {
+            Some(files) if files.iter().any(|x| x == "none") => Vec::new(),
+            Some(files) => files.iter().map(|x| Self::expand_home(x)).collect(),
+            None => dirs::home_dir()
+                .map(|x| vec![x.join(".ssh").join("known_hosts")])
+                .unwrap_or_default(),
+        }
+    }
+
+    /// Resolve private key files from `IdentityFile`, expanding its tokens.
+    /// If none is set, the default keys in `~/.ssh` are used
+    fn resolve_identity_files(params: &HostParams, opts: &SshOpts) -> Vec<PathBuf> {
+        match params.identity_file.as_deref() {
+            Some(files) => files
+                .iter()
+                .map(|x| {
+                    Self::expand_home(&Self::expand_tokens(&x.to_string_lossy(), params, opts))
+                })
+                .collect(),
+            None => dirs::home_dir()
+                .map(|home| {
+                    ["id_ed25519", "id_ecdsa", "id_rsa"]
+                        .iter()
+                        .map(|x| home.join(".ssh").join(x))
+                        .collect()
+                })
+                .unwrap_or_default(),
+        }
+    }
+
+    /// Resolve user certificate from `CertificateFile`, expanding its tokens
+    fn resolve_certificate_file(params: &HostParams, opts: &SshOpts) -> Option<PathBuf> {
+        params
+            .certificate_file
+            .as_deref()
+            .map(|x| Self::expand_home(&Self::expand_tokens(&x.to_string_lossy(), params, opts)))
+    }
+
+    /// Resolve jump hosts from `ProxyJump`; `none` means no jump host.
+    /// A proxy command set in opts overrides `ProxyJump`
+    fn resolve_proxy_jump(directives: &Directives, opts: &SshOpts) -> RemoteResult<Vec<JumpHost>> {
+        if opts.proxy_command.is_some() {
+            return Ok(Vec::new());
+        }
+        match directives.get("proxyjump").and_then(|x| x.first()) {
+            None => Ok(Vec::new()),
+            Some(jumps) if jumps.eq_ignore_ascii_case("none") => Ok(Vec::new()),
+            Some(jumps) => jumps
+                .split(',')
+                .map(|x| {
+                    JumpHost::from_str(x).map_err(|e| {
+                        RemoteError::new_ex(
+                            RemoteErrorType::BadAddress,
+                            format!("Could not parse ProxyJump: {e}"),
+                        )
+                    })
+                })
+                .collect(),
+        }
+    }
+
+    /// Resolve proxy command from opts and `ProxyCommand`, expanding its tokens; `none` means no proxy command
+    fn resolve_proxy_command(
+        directives: &Directives,
+        params: &HostParams,
+        opts: &SshOpts,
+    ) -> RemoteResult<Option<String>> {
+        let command = match opts
+            .proxy_command
+            .as_deref()
+            .or_else(|| directives.get("proxycommand")?.first().map(String::as_str))
+        {
+            Some(command) => command,
+            None => return Ok(None),
+        };
+        if command.eq_ignore_ascii_case("none") {
+            return Ok(None);
+        }
+        Self::expand_command_tokens(command, params, opts).map(Some)
+    }
+
+    /// Resolve `IdentitiesOnly`; defaults to `false`
+    fn resolve_identities_only(directives: &Directives) -> bool {
+        directives
+            .get("identitiesonly")
+            .and_then(|x| x.first())
+            .map(|x| x.eq_ignore_ascii_case("yes"))
+            .unwrap_or(false)
+    }
+
+    /// Returns whether `ForwardAgent` requests the agent forwarding, with `yes` or an agent socket
+    fn requests_forward_agent(directives: &Directives) -> bool {
+        directives
+            .get("forwardagent")
+            .and_then(|x| x.first())
+            .map(|x| !x.eq_ignore_ascii_case("no"))
+            .unwrap_or(false)
+    }
+
+    /// Resolve ssh agent socket from opts and `IdentityAgent`, expanding its tokens and environment variable.
+    /// `SSH_AUTH_SOCK` or none set means the default agent, `none` disables the agent
+    fn resolve_identity_agent(
+        directives: &Directives,
+        params: &HostParams,
+        opts: &SshOpts,
+    ) -> IdentityAgent {
+        if let Some(p) = opts.identity_agent.as_deref() {
+            return IdentityAgent::Socket(p.to_path_buf());
+        }
+        match directives.get("identityagent").and_then(|x| x.first()) {
+            None => IdentityAgent::Default,
+            Some(agent) if agent == "SSH_AUTH_SOCK" => IdentityAgent::Default,
+            Some(agent) if agent.eq_ignore_ascii_case("none") => IdentityAgent::Disabled,
+            Some(agent) => match agent.strip_prefix('$') {
+                Some(var) => match std::env::var_os(var) {
+                    Some(socket) => IdentityAgent::Socket(PathBuf::from(socket)),
+                    None => {
+                        warn!("IdentityAgent variable '{var}' is not set; ssh agent is disabled");
+                        IdentityAgent::Disabled
+                    }
+                },
+                None => IdentityAgent::Socket(Self::expand_home(&Self::expand_tokens(
+                    agent, params, opts,
+                ))),
+            },
+        }
+    }
+
+    /// Expand the tokens of `s`, as the ssh client does:
+    ///
+    /// - `%%`: a literal `%`
+    /// - `%d`: local user home directory
+    /// - `%h`: remote host name
+    /// - `%n`: host name as provided to ssh options
+    /// - `%p`: remote port
+    /// - `%r`: remote username
+    /// - `%u`: local username
+    fn expand_tokens(s: &str, params: &HostParams, opts: &SshOpts) -> String {
+        Self::substitute_tokens(s, params, opts, |_, _| {})
+    }
+
+    /// Expand the tokens of `s`, calling `substituted` with each token actually substituted and its value
+    fn substitute_tokens(
+        s: &str,
+        params: &HostParams,
+        opts: &SshOpts,
+        mut substituted: impl FnMut(char, &str),
+    ) -> String {
+        let mut expanded = String::with_capacity(s.len());
+        let mut chars = s.chars();
+        while let Some(c) = chars.next() {
+            if c != '%' {
+                expanded.push(c);
+                continue;
+            }
+            let (token, value) = match chars.next() {
+                Some('%') => {
+                    expanded.push('%');
+                    continue;
+                }
+                Some('d') => (
+                    'd',
+                    dirs::home_dir()
+                        .map(|x| x.to_string_lossy().to_string())
+                        .unwrap_or_default(),
+                ),
+                Some('h') => ('h', Self::resolve_host(params, opts)),
+                Some('n') => ('n', opts.host.clone()),
+                Some('p') => ('p', Self::resolve_port(params, opts).to_string()),
+                Some('r') => ('r', Self::resolve_username(params, opts)),
+                Some('u') => ('u', Self::local_username()),
+                Some(other) => {
+                    warn!("Unknown token '%{other}' in '{s}'; leaving it as is");
+                    expanded.push('%');
+                    expanded.push(other);
+                    continue;
+                }
+                None => {
+                    expanded.push('%');
+                    continue;
+                }
+            };
+            substituted(token, &value);
+            expanded.push_str(&value);
+        }
+        expanded
+    }
+
+    /// Expand the tokens of the command line `command`, which is run with the system shell.
+    ///
+    /// As in OpenSSH, the host and user names substituted for `%h`, `%n`, `%r` and `%u` must not contain
+    /// shell metacharacters or whitespaces, since they would be interpreted by the shell
+    fn expand_command_tokens(
+        command: &str,
+        params: &HostParams,
+        opts: &SshOpts,
+    ) -> RemoteResult<String> {
+        // only the tokens actually substituted are checked; `%%h` is a literal `%h`
+        let mut refused: Option<(char, String)> = None;
+        let expanded = Self::substitute_tokens(command, params, opts, |token, value| {
+            let unsafe_value = value.starts_with('-')
+                || value.chars().any(|c| {
+                    SHELL_METACHARACTERS.contains(c) || c.is_whitespace() || c.is_control()
+                });
+            if refused.is_none() && matches!(token, 'h' | 'n' | 'r' | 'u') && unsafe_value {
+                refused = Some((token, value.to_string()));
+            }
+        });
+        match refused {
+            Some((token, value)) => {
+                error!("Refusing to substitute '{value}' for %{token} in '{command}'");
+                Err(RemoteError::new_ex(
+                    RemoteErrorType::BadAddress,
+                    format!("'{value}' substituted for %{token} in '{command}' contains shell metacharacters"),
+                ))
+            }
+            None => Ok(expanded),
+        }
+    }
+
+    /// Get the name of the local user
+    fn local_username() -> String {
+        std::env::var("USER")
+            .or_else(|_| std::env::var("USERNAME"))
+            .unwrap_or_default()
+    }
+
+    /// Expand leading `~` to the user home directory
+    fn expand_home(p: &str) -> PathBuf {
+        match (p.strip_prefix('~'), dirs::home_dir()) {
+            (Some(rest), Some(home)) => home.join(rest.trim_start_matches('/')),
+            _ => PathBuf::from(p),
+        }
     }
 }
 
@@ -115,10 +656,11 @@ impl TryFrom<&SshOpts> for Config {
     fn try_from(opts: &SshOpts) -> Result<Self, Self::Error> {
         if let Some(p) = opts.config_file.as_deref() {
             let params = Self::parse(p, opts.host.as_str(), opts.parse_rules)?;
-            Ok(Self::from_params(params, opts))
+            let directives = Self::parse_directives(p, opts.host.as_str())?;
+            Self::from_params(params, directives, opts)
         } else {
             let params = HostParams::default();
-            Ok(Self::from_params(params, opts))
+            Self::from_params(params, Directives::new(), opts)
         }
     }
 }
@@ -130,6 +672,7 @@ mod test {
 
     use super::*;
     use crate::mock::ssh as ssh_mock;
+    use crate::RetryPolicy;
 
     #[test]
     fn should_init_config_from_default_ssh_opts() {
@@ -141,6 +684,7 @@ mod test {
         assert_eq!(config.host.as_str(), "192.168.1.1");
         assert!(config.username.is_empty());
         assert_eq!(config.params, HostParams::default());
+        assert_eq!(config.host_key_check, HostKeyCheck::AcceptNew);
     }
 
     #[test]
@@ -179,9 +723,10 @@ mod test {
             .config_file(config_file.path(), ParseRule::STRICT)
             .connection_timeout(Duration::from_secs(10))
             .port(22)
-            .username("omar");
+            .username("omar")
+            .retry_policy(RetryPolicy::default().max_attempts(5));
         let config = Config::try_from(&opts).ok().unwrap();
-        assert_eq!(config.connection_attempts, 3);
+        assert_eq!(config.connection_attempts, 5);
         assert_eq!(config.connection_timeout, Duration::from_secs(10));
         assert_eq!(config.host.as_str(), "sftp");
         assert_eq!(config.resolved_host.as_str(), "127.0.0.1");
@@ -189,4 +734,335 @@ mod test {
         assert_eq!(config.username.as_str(), "omar");
         assert_ne!(config.params, HostParams::default());
     }
+
+    #[test]
+    fn should_read_unsupported_directives() {
+        let config_file = ssh_mock::create_ssh_config();
+        let directives = Config::parse_directives(config_file.path(), "trusted").unwrap();
+        assert_eq!(
+            directives.get("stricthostkeychecking").unwrap(),
+            &vec!["accept-new".to_string()]
+        );
+        assert_eq!(
+            directives.get("userknownhostsfile").unwrap(),
+            &vec!["/tmp/known_hosts".to_string()]
+        );
+        let directives = Config::parse_directives(config_file.path(), "sftp").unwrap();
+        assert_eq!(
+            directives.get("stricthostkeychecking").unwrap(),
+            &vec!["no".to_string()]
+        );
+        assert!(!directives.contains_key("userknownhostsfile"));
+        let directives = Config::parse_directives(config_file.path(), "internal").unwrap();
+        assert!(!directives.contains_key("stricthostkeychecking"));
+        assert_eq!(
+            directives.get("connectionattempts").unwrap(),
+            &vec!["3".to_string()]
+        );
+    }
+
+    #[test]
+    fn should_resolve_identity_files() {
+        let config_file = ssh_mock::create_ssh_config();
+        let opts = SshOpts::new("sftp").config_file(config_file.path(), ParseRule::STRICT);
+        let config = Config::try_from(&opts).ok().unwrap();
+        assert_eq!(
+            config.identity_files,
+            vec![
+                PathBuf::from("/tmp/keys/127.0.0.1/sftp"),
+                PathBuf::from("/tmp/keys/sftp-10022"),
+            ]
+        );
+        assert!(config.identities_only);
+        assert_eq!(
+            config.certificate_file.as_deref().unwrap(),
+            Path::new("/tmp/keys/sftp-cert.pub")
+        );
+        // default keys
+        let opts = SshOpts::new("scp").config_file(config_file.path(), ParseRule::STRICT);
+        let config = Config::try_from(&opts).ok().unwrap();
+        let home = dirs::home_dir().unwrap();
+        assert_eq!(
+            config.identity_files,
+            vec![
+                home.join(".ssh/id_ed25519"),
+                home.join(".ssh/id_ecdsa"),
+                home.join(".ssh/id_rsa"),
+            ]
+        );
+        assert!(!config.identities_only);
+        assert!(config.certificate_file.is_none());
+    }
+
+    #[test]
+    fn should_resolve_identity_agent() {
+        let config_file = ssh_mock::create_ssh_config();
+        let opts = SshOpts::new("proxied").config_file(config_file.path(), ParseRule::STRICT);
+        let config = Config::try_from(&opts).ok().unwrap();
+        assert_eq!(
+            config.identity_agent,
+            IdentityAgent::Socket(dirs::home_dir().unwrap().join(".ssh/agent-sftp.sock"))
+        );
+        let opts = SshOpts::new("trusted").config_file(config_file.path(), ParseRule::STRICT);
+        let config = Config::try_from(&opts).ok().unwrap();
+        assert_eq!(config.identity_agent, IdentityAgent::Default);
+        let opts = SshOpts::new("scp").config_file(config_file.path(), ParseRule::STRICT);
+        let config = Config::try_from(&opts).ok().unwrap();
+        assert_eq!(config.identity_agent, IdentityAgent::Default);
+        let opts = SshOpts::new("internal").config_file(config_file.path(), ParseRule::STRICT);
+        let config = Config::try_from(&opts).ok().unwrap();
+        assert_eq!(config.identity_agent, IdentityAgent::Disabled);
+        // opts override the configuration
+        let opts = SshOpts::new("sftp")
+            .config_file(config_file.path(), ParseRule::STRICT)
+            .identity_agent("/run/agent.sock");
+        let config = Config::try_from(&opts).ok().unwrap();
+        assert_eq!(
+            config.identity_agent,
+            IdentityAgent::Socket(PathBuf::from("/run/agent.sock"))
+        );
+    }
+
+    #[test]
+    fn should_ignore_forward_agent() {
+        let config_file = ssh_mock::create_ssh_config();
+        // the agent forwarding is not supported, but doesn't prevent connecting
+        let opts = SshOpts::new("proxied").config_file(config_file.path(), ParseRule::STRICT);
+        let config = Config::try_from(&opts).ok().unwrap();
+        assert!(Config::requests_forward_agent(&config.directives));
+        let opts = SshOpts::new("sftp").config_file(config_file.path(), ParseRule::STRICT);
+        let config = Config::try_from(&opts).ok().unwrap();
+        assert!(!Config::requests_forward_agent(&config.directives));
+    }
+
+    #[test]
+    fn should_expand_tokens() {
+        let opts = SshOpts::new("sftp").port(2222).username("omar");
+        let params = HostParams {
+            host_name: Some("192.168.1.1".to_string()),
+            ..Default::default()
+        };
+        assert_eq!(
+            Config::expand_tokens("%h_%n_%p_%r_100%%_%x", &params, &opts).as_str(),
+            "192.168.1.1_sftp_2222_omar_100%_%x"
+        );
+        let home = dirs::home_dir().unwrap();
+        assert_eq!(
+            PathBuf::from(Config::expand_tokens("%d/.ssh/id_rsa", &params, &opts)),
+            home.join(".ssh/id_rsa")
+        );
+    }
+
+    #[test]
+    fn should_parse_jump_host() {
+        assert_eq!(
+            JumpHost::from_str("bastion").unwrap(),
+            JumpHost {
+                host: "bastion".to_string(),
+                port: None,
+                username: None
+            }
+        );
+        assert_eq!(
+            JumpHost::from_str("ssh://omar@bastion:2222").unwrap(),
+            JumpHost {
+                host: "bastion".to_string(),
+                port: Some(2222),
+                username: Some("omar".to_string())
+            }
+        );
+        assert_eq!(
+            JumpHost::from_str("omar@[::1]:2222").unwrap(),
+            JumpHost {
+                host: "::1".to_string(),
+                port: Some(2222),
+                username: Some("omar".to_string())
+            }
+        );
+        assert_eq!(JumpHost::from_str("[::1]").unwrap().host.as_str(), "::1");
+        assert!(JumpHost::from_str("omar@").is_err());
+        assert!(JumpHost::from_str("bastion:port").is_err());
+        assert!(JumpHost::from_str("[::1").is_err());
+    }
+
+    #[test]
+    fn should_resolve_proxy_jump_from_file() {
+        let config_file = ssh_mock::create_ssh_config();
+        let opts = SshOpts::new("internal").config_file(config_file.path(), ParseRule::STRICT);
+        let config = Config::try_from(&opts).ok().unwrap();
+        assert_eq!(
+            config.proxy_jump,
+            vec![
+                JumpHost {
+                    host: "sftp".to_string(),
+                    port: None,
+                    username: None
+                },
+                JumpHost {
+                    host: "10.0.0.1".to_string(),
+                    port: Some(2222),
+                    username: Some("omar".to_string())
+                },
+            ]
+        );
+        let opts = SshOpts::new("sftp").config_file(config_file.path(), ParseRule::STRICT);
+        let config = Config::try_from(&opts).ok().unwrap();
+        assert!(config.proxy_jump.is_empty());
+    }
+
+    #[test]
+    fn should_resolve_proxy_command_from_file() {
+        let config_file = ssh_mock::create_ssh_config();
+        let opts = SshOpts::new("proxied").config_file(config_file.path(), ParseRule::STRICT);
+        let config = Config::try_from(&opts).ok().unwrap();
+        assert_eq!(
+            config.proxy_command.as_deref().unwrap(),
+            "nc -X connect -x proxy:3128 127.0.0.1 10022 % sftp"
+        );
+        let opts = SshOpts::new("proxied")
+            .config_file(config_file.path(), ParseRule::STRICT)
+            .proxy_command("none");
+        let config = Config::try_from(&opts).ok().unwrap();
+        assert!(config.proxy_command.is_none());
+        // proxy command in opts overrides ProxyJump
+        let opts = SshOpts::new("internal")
+            .config_file(config_file.path(), ParseRule::STRICT)
+            .proxy_command("cloudflared access ssh --hostname %h");
+        let config = Config::try_from(&opts).ok().unwrap();
+        assert!(config.proxy_jump.is_empty());
+        assert_eq!(
+            config.proxy_command.as_deref().unwrap(),
+            "cloudflared access ssh --hostname 10.0.0.2"
+        );
+    }
+
+    #[test]
+    fn should_keep_proxy_command_as_written() {
+        use std::io::Write;
+
+        let mut config_file = tempfile::NamedTempFile::new().unwrap();
+        writeln!(
+            config_file,
+            "Host bastioned\n    HostName 10.0.0.3\n    ProxyCommand  sh -c 'nc  %h %p' # 2>/dev/null"
+        )
+        .unwrap();
+        let opts = SshOpts::new("bastioned").config_file(config_file.path(), ParseRule::STRICT);
+        let config = Config::try_from(&opts).ok().unwrap();
+        assert_eq!(
+            config.proxy_command.as_deref().unwrap(),
+            "sh -c 'nc  10.0.0.3 22' # 2>/dev/null"
+        );
+    }
+
+    #[test]
+    fn should_not_substitute_shell_metacharacters_in_proxy_command() {
+        for (host, username) in [
+            ("example.com;touch /tmp/pwned", "omar"),
+            ("$(touch /tmp/pwned)", "omar"),
+            ("-oProxyCommand=id", "omar"),
+            ("example.com", "omar`id`"),
+            ("example.com", "omar veeso"),
+        ] {
+            let opts = SshOpts::new(host)
+                .username(username)
+                .proxy_command("nc -X connect -x proxy:3128 %n %p %r");
+            assert_eq!(
+                Config::try_from(&opts).err().unwrap().kind,
+                RemoteErrorType::BadAddress
+            );
+        }
+        // values which are not substituted are not checked
+        let opts = SshOpts::new("example.com")
+            .username("omar`id`")
+            .proxy_command("nc %h %p");
+        assert_eq!(
+            Config::try_from(&opts).ok().unwrap().proxy_command.unwrap(),
+            "nc example.com 22"
+        );
+        // escaped tokens are not substituted
+        let opts = SshOpts::new("example.com;touch /tmp/pwned")
+            .username("omar`id`")
+            .proxy_command("echo %%h %%n %%r >&2; nc proxy 3128");
+        assert_eq!(
+            Config::try_from(&opts).ok().unwrap().proxy_command.unwrap(),
+            "echo %h %n %r >&2; nc proxy 3128"
+        );
+    }
+
+    #[test]
+    fn should_resolve_server_alive_from_file() {
+        let config_file = ssh_mock::create_ssh_config();
+        let opts = SshOpts::new("proxied").config_file(config_file.path(), ParseRule::STRICT);
+        let config = Config::try_from(&opts).ok().unwrap();
+        assert_eq!(config.server_alive_interval, Some(Duration::from_secs(15)));
+        assert_eq!(config.server_alive_count_max, 5);
+        // opts override
+        let opts = SshOpts::new("proxied")
+            .config_file(config_file.path(), ParseRule::STRICT)
+            .server_alive_interval(Duration::ZERO)
+            .server_alive_count_max(2);
+        let config = Config::try_from(&opts).ok().unwrap();
+        assert!(config.server_alive_interval.is_none());
+        assert_eq!(config.server_alive_count_max, 2);
+        // defaults
+        let config = Config::try_from(&SshOpts::new("localhost")).ok().unwrap();
+        assert!(config.server_alive_interval.is_none());
+        assert_eq!(config.server_alive_count_max, 3);
+    }
+
+    #[test]
+    fn should_resolve_ipv6_address() {
+        let config = Config::try_from(&SshOpts::new("::1")).ok().unwrap();
+        assert_eq!(config.resolved_host.as_str(), "::1");
+        assert_eq!(config.address.as_str(), "[::1]:22");
+        let config = Config::try_from(&SshOpts::new("[fe80::1%eth0]").port(2222))
+            .ok()
+            .unwrap();
+        assert_eq!(config.resolved_host.as_str(), "fe80::1%eth0");
+        assert_eq!(config.address.as_str(), "[fe80::1%eth0]:2222");
+        let config = Config::try_from(&SshOpts::new("127.0.0.1")).ok().unwrap();
+        assert_eq!(config.address.as_str(), "127.0.0.1:22");
+        assert_eq!(config.address_family, AddressFamily::Any);
+        assert!(config.bind_address.is_none());
+    }
+
+    #[test]
+    fn should_resolve_address_family_and_bind_address_from_file() {
+        let config_file = ssh_mock::create_ssh_config();
+        let opts = SshOpts::new("loopback6").config_file(config_file.path(), ParseRule::STRICT);
+        let config = Config::try_from(&opts).ok().unwrap();
+        assert_eq!(config.address.as_str(), "[::1]:10022");
+        assert_eq!(config.address_family, AddressFamily::Inet6);
+        assert_eq!(config.bind_address, Some(IpAddr::from_str("::1").unwrap()));
+        // opts override
+        let opts = SshOpts::new("loopback6")
+            .config_file(config_file.path(), ParseRule::STRICT)
+            .address_family(AddressFamily::Any)
+            .bind_address(IpAddr::from_str("::").unwrap());
+        let config = Config::try_from(&opts).ok().unwrap();
+        assert_eq!(config.address_family, AddressFamily::Any);
+        assert_eq!(config.bind_address, Some(IpAddr::from_str("::").unwrap()));
+    }
+
+    #[test]
+    fn should_resolve_host_key_check_from_file() {
+        let config_file = ssh_mock::create_ssh_config();
+        let opts = SshOpts::new("trusted").config_file(config_file.path(), ParseRule::STRICT);
+        let config = Config::try_from(&opts).ok().unwrap();
+        assert_eq!(config.host_key_check, HostKeyCheck::AcceptNew);
+        assert_eq!(
+            config.known_hosts_files,
+            vec![PathBuf::from("/tmp/known_hosts")]
+        );
+        let opts = SshOpts::new("trusted")
+            .config_file(config_file.path(), ParseRule::STRICT)
+            .host_key_check(HostKeyCheck::Strict)
+            .known_hosts_file("/home/omar/.ssh/known_hosts");
+        let config = Config::try_from(&opts).ok().unwrap();
+        assert_eq!(config.host_key_check, HostKeyCheck::Strict);
+        assert_eq!(
+            config.known_hosts_files,
+            vec![PathBuf::from("/home/omar/.ssh/known_hosts")]
+        );
+    }
 }
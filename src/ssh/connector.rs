@@ -0,0 +1,81 @@
+//! ## Connector
+//!
+//! custom transports to run ssh sessions over
+
+use std::cell::Cell;
+use std::io;
+
+use super::tunnel::SessionStream;
+
+/// This trait can be implemented to establish the stream the ssh session runs over,
+/// instead of connecting to the server with tcp (e.g. with a unix socket to a local forwarder).
+///
+/// When a connector is set, host resolution, jump hosts, proxy command and proxy are not used;
+/// the handshake, the algorithms preferences and the authentication apply as usual.
+pub trait SshConnector: Send {
+    /// Establish the stream to the ssh server.
+    ///
+    /// `host` and `port` are the address of the server, as resolved from the ssh configuration.
+    fn connect(&self, host: &str, port: u16) -> io::Result<Box<dyn SessionStream>>;
+}
+
+/// Connector which provides an already connected stream, only once
+pub(crate) struct StreamConnector {
+    stream: Cell<Option<Box<dyn SessionStream>>>,
+}
+
+impl StreamConnector {
+    pub fn new(stream: Box<dyn SessionStream>) -> Self {
+        Self {
+            stream: Cell::new(Some(stream)),
+        }
+    }
+}
+
+impl SshConnector for StreamConnector {
+    fn connect(&self, _host: &str, _port: u16) -> io::Result<Box<dyn SessionStream>> {
+        self.stream.take().ok_or_else(|| {
+            io::Error::new(
+                io::ErrorKind::NotConnected,
+                "the provided stream has already been used",
+            )
+        })
+    }
+}
+
+/// Stream returned by a connector, which can be provided to the session
+pub(crate) struct ConnectorStream(pub Box<dyn SessionStream>);
+
+#[cfg(unix)]
+impl std::os::unix::io::AsRawFd for ConnectorStream {
+    fn as_raw_fd(&self) -> std::os::unix::io::RawFd {
+        self.0.as_raw_fd()
+    }
+}
+
+#[cfg(windows)]
+impl std::os::windows::io::AsRawSocket for ConnectorStream {
+    fn as_raw_socket(&self) -> std::os::windows::io::RawSocket {
+        self.0.as_raw_socket()
+    }
+}
+
+#[cfg(test)]
+mod test {
+
+    use pretty_assertions::assert_eq;
+
+    use super::*;
+    use crate::ssh::tunnel::socket_pair;
+
+    #[test]
+    fn should_provide_stream_only_once() {
+        let (stream, _) = socket_pair().unwrap();
+        let connector = StreamConnector::new(Box::new(stream));
+        assert!(connector.connect("localhost", 22).is_ok());
+        assert_eq!(
+            connector.connect("localhost", 22).err().unwrap().kind(),
+            io::ErrorKind::NotConnected
+        );
+    }
+}
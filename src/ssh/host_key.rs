@@ -0,0 +1,573 @@
+//! ## Host key
+//!
+//! server host key verification
+
+use std::fs::{self, OpenOptions};
+use std::io::Write;
+use std::path::{Path, PathBuf};
+
+use base64::engine::general_purpose::STANDARD_NO_PAD;
+use base64::Engine as _;
+use remotefs::{RemoteError, RemoteErrorType, RemoteResult};
+use ssh2::{
+    CheckResult, HashType, HostKeyType as SshHostKeyType, KnownHostFileKind, KnownHostKeyFormat,
+    Session,
+};
+
+/// Host key checking policy; equivalent of `StrictHostKeyChecking` in ssh configuration.
+///
+/// When the verification fails, `connect` returns a `RemoteErrorType::SslError`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum HostKeyCheck {
+    /// Refuse to connect to hosts whose key is unknown or has changed
+    Strict,
+    /// Add keys of unknown hosts to the known hosts file, but refuse to connect to hosts whose key has changed
+    AcceptNew,
+    /// Don't check host keys
+    Off,
+}
+
+/// Re-implementation of ssh host key type, in order to use `Eq`
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum HostKeyType {
+    Unknown,
+    Rsa,
+    Dss,
+    Ecdsa256,
+    Ecdsa384,
+    Ecdsa521,
+    Ed25519,
+}
+
+impl HostKeyType {
+    /// Key type name in ssh protocol syntax (e.g. `ssh-ed25519`)
+    pub fn name(&self) -> &'static str {
+        match self {
+            HostKeyType::Unknown => "unknown",
+            HostKeyType::Rsa => "ssh-rsa",
+            HostKeyType::Dss => "ssh-dss",
+            HostKeyType::Ecdsa256 => "ecdsa-sha2-nistp256",
+            HostKeyType::Ecdsa384 => "ecdsa-sha2-nistp384",
+            HostKeyType::Ecdsa521 => "ecdsa-sha2-nistp521",
+            HostKeyType::Ed25519 => "ssh-ed25519",
+        }
+    }
+}
+
+impl From<SshHostKeyType> for HostKeyType {
+    fn from(t: SshHostKeyType) -> Self {
+        match t {
+            SshHostKeyType::Unknown => HostKeyType::Unknown,
+            SshHostKeyType::Rsa => HostKeyType::Rsa,
+            SshHostKeyType::Dss => HostKeyType::Dss,
+            SshHostKeyType::Ecdsa256 => HostKeyType::Ecdsa256,
+            SshHostKeyType::Ecdsa384 => HostKeyType::Ecdsa384,
+            SshHostKeyType::Ecdsa521 => HostKeyType::Ecdsa521,
+            SshHostKeyType::Ed25519 => HostKeyType::Ed25519,
+        }
+    }
+}
+
+impl From<HostKeyType> for KnownHostKeyFormat {
+    fn from(t: HostKeyType) -> Self {
+        match t {
+            HostKeyType::Unknown => KnownHostKeyFormat::Unknown,
+            HostKeyType::Rsa => KnownHostKeyFormat::SshRsa,
+            HostKeyType::Dss => KnownHostKeyFormat::SshDss,
+            HostKeyType::Ecdsa256 => KnownHostKeyFormat::Ecdsa256,
+            HostKeyType::Ecdsa384 => KnownHostKeyFormat::Ecdsa384,
+            HostKeyType::Ecdsa521 => KnownHostKeyFormat::Ecdsa521,
+            HostKeyType::Ed25519 => KnownHostKeyFormat::Ed25519,
+        }
+    }
+}
+
+/// Host key presented by the server during the handshake
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct HostKey {
+    /// Host the key belongs to
+    pub host: String,
+    /// Port the server is listening on
+    pub port: u16,
+    /// Key type
+    pub key_type: HostKeyType,
+    /// Raw public key
+    pub key: Vec<u8>,
+    /// SHA256 fingerprint, in the same format used by OpenSSH (`SHA256:...`)
+    pub sha256_fingerprint: String,
+    /// MD5 fingerprint, in the same format used by OpenSSH (`MD5:xx:xx:...`)
+    pub md5_fingerprint: String,
+}
+
+impl HostKey {
+    /// Instantiates a new `HostKey` from the raw key and its SHA256 and MD5 hashes
+    pub(crate) fn new(
+        host: &str,
+        port: u16,
+        key: &[u8],
+        key_type: HostKeyType,
+        sha256: &[u8],
+        md5: &[u8],
+    ) -> Self {
+        Self {
+            host: host.to_string(),
+            port,
+            key_type,
+            key: key.to_vec(),
+            sha256_fingerprint: format!("SHA256:{}", STANDARD_NO_PAD.encode(sha256)),
+            md5_fingerprint: format!(
+                "MD5:{}",
+                md5.iter()
+                    .map(|x| format!("{x:02x}"))
+                    .collect::<Vec<String>>()
+                    .join(":")
+            ),
+        }
+    }
+
+    /// Returns whether `fingerprint` is either the SHA256 or the MD5 fingerprint of this key
+    pub fn matches_fingerprint(&self, fingerprint: &str) -> bool {
+        self.sha256_fingerprint == fingerprint
+            || self.md5_fingerprint.eq_ignore_ascii_case(fingerprint)
+    }
+}
+
+/// Result of the host key verification performed by a `HostKeyVerifier`
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum HostKeyVerdict {
+    /// Trust the host key for this connection only
+    Accept,
+    /// Don't trust the host key; the connection is aborted
+    Reject,
+    /// Trust the host key and write it to the known hosts file
+    AcceptAndRemember,
+}
+
+/// This trait can be implemented in order to decide whether to trust the host key of a server.
+///
+/// The verifier is invoked after the handshake:
+///
+/// - if host key checking is `Off`, on every connection
+/// - otherwise, only when the host is not in the known hosts files. Changed host keys are always rejected.
+pub trait HostKeyVerifier: Send {
+    /// Decide whether to trust `host_key`
+    fn verify(&self, host_key: &HostKey) -> HostKeyVerdict;
+}
+
+/// A `HostKeyVerifier` which accepts only the host keys matching one of the provided fingerprints.
+///
+/// Fingerprints can be provided either in `SHA256:...` or in `MD5:xx:xx:...` format.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct PinnedFingerprintVerifier {
+    fingerprints: Vec<String>,
+}
+
+impl PinnedFingerprintVerifier {
+    /// Instantiates a new `PinnedFingerprintVerifier` with the provided fingerprints
+    pub fn new<S: AsRef<str>>(fingerprints: &[S]) -> Self {
+        Self {
+            fingerprints: fingerprints
+                .iter()
+                .map(|x| x.as_ref().to_string())
+                .collect(),
+        }
+    }
+}
+
+impl HostKeyVerifier for PinnedFingerprintVerifier {
+    fn verify(&self, host_key: &HostKey) -> HostKeyVerdict {
+        if self
+            .fingerprints
+            .iter()
+            .any(|x| host_key.matches_fingerprint(x))
+        {
+            HostKeyVerdict::Accept
+        } else {
+            HostKeyVerdict::Reject
+        }
+    }
+}
+
+/// A `HostKeyVerifier` which accepts any host key. Use with care.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct AlwaysAcceptVerifier;
+
+impl HostKeyVerifier for AlwaysAcceptVerifier {
+    fn verify(&self, _host_key: &HostKey) -> HostKeyVerdict {
+        HostKeyVerdict::Accept
+    }
+}
+
+/// Verify the host key of the server on the current session against the provided known hosts files
+/// and the host key verifier, if any
+pub fn verify_host_key(
+    session: &Session,
+    host: &str,
+    port: u16,
+    policy: HostKeyCheck,
+    known_hosts_files: &[PathBuf],
+    verifier: Option<&dyn HostKeyVerifier>,
+) -> RemoteResult<()> {
+    if policy == HostKeyCheck::Off && verifier.is_none() {
+        trace!("Host key checking is disabled");
+        return Ok(());
+    }
+    let host_key = session_host_key(session, host, port).ok_or_else(|| {
+        RemoteError::new_ex(
+            RemoteErrorType::ProtocolError,
+            "could not get host key from server",
+        )
+    })?;
+    debug!(
+        "Server host key: {} {}",
+        host_key.key_type.name(),
+        host_key.sha256_fingerprint
+    );
+    check_host_key(session, &host_key, policy, known_hosts_files, verifier)
+}
+
+/// Get the host key presented by the server of `host:port` during the handshake of `session`
+pub(crate) fn session_host_key(session: &Session, host: &str, port: u16) -> Option<HostKey> {
+    let (key, key_type) = session.host_key()?;
+    Some(HostKey::new(
+        host,
+        port,
+        key,
+        HostKeyType::from(key_type),
+        session.host_key_hash(HashType::Sha256).unwrap_or_default(),
+        session.host_key_hash(HashType::Md5).unwrap_or_default(),
+    ))
+}
+
+/// Check `host_key` against the known hosts files and the verifier, applying `policy` if the host is unknown
+fn check_host_key(
+    session: &Session,
+    host_key: &HostKey,
+    policy: HostKeyCheck,
+    known_hosts_files: &[PathBuf],
+    verifier: Option<&dyn HostKeyVerifier>,
+) -> RemoteResult<()> {
+    let (host, port) = (host_key.host.as_str(), host_key.port);
+    if policy == HostKeyCheck::Off {
+        return apply_verifier(session, host_key, known_hosts_files, verifier);
+    }
+    let mut known_hosts = session
+        .known_hosts()
+        .map_err(|e| RemoteError::new_ex(RemoteErrorType::ProtocolError, e))?;
+    for file in known_hosts_files.iter().filter(|x| x.exists()) {
+        trace!("Reading known hosts at {}", file.display());
+        if let Err(err) = known_hosts.read_file(file, KnownHostFileKind::OpenSSH) {
+            error!("Could not read known hosts at {}: {}", file.display(), err);
+            return Err(RemoteError::new_ex(
+                RemoteErrorType::IoError,
+                format!("could not read known hosts file {}: {err}", file.display()),
+            ));
+        }
+    }
+    match known_hosts.check_port(host, port, &host_key.key) {
+        CheckResult::Match => {
+            debug!("Host key for {host}:{port} matches known hosts");
+            Ok(())
+        }
+        CheckResult::Mismatch => {
+            error!("Host key for {host}:{port} does NOT match known hosts");
+            Err(RemoteError::new_ex(
+                RemoteErrorType::SslError,
+                format!("host key mismatch for {host}:{port}; possible man-in-the-middle attack"),
+            ))
+        }
+        CheckResult::NotFound if verifier.is_some() => {
+            apply_verifier(session, host_key, known_hosts_files, verifier)
+        }
+        CheckResult::NotFound if policy == HostKeyCheck::AcceptNew => {
+            info!("Host {host}:{port} is unknown; adding its key to known hosts");
+            remember_host_key(session, host_key, known_hosts_files)
+        }
+        CheckResult::NotFound => {
+            error!("Host {host}:{port} is not in known hosts");
+            Err(RemoteError::new_ex(
+                RemoteErrorType::SslError,
+                format!("no host key is known for {host}:{port}"),
+            ))
+        }
+        CheckResult::Failure => Err(RemoteError::new_ex(
+            RemoteErrorType::ProtocolError,
+            format!("could not check host key for {host}:{port}"),
+        )),
+    }
+}
+
+/// Ask `verifier` whether to trust `host_key`. If no verifier is provided, the key is trusted
+fn apply_verifier(
+    session: &Session,
+    host_key: &HostKey,
+    known_hosts_files: &[PathBuf],
+    verifier: Option<&dyn HostKeyVerifier>,
+) -> RemoteResult<()> {
+    let verdict = verifier
+        .map(|x| x.verify(host_key))
+        .unwrap_or(HostKeyVerdict::Accept);
+    debug!(
+        "Host key verifier verdict for {}:{}: {:?}",
+        host_key.host, host_key.port, verdict
+    );
+    match verdict {
+        HostKeyVerdict::Accept => Ok(()),
+        HostKeyVerdict::AcceptAndRemember => {
+            remember_host_key(session, host_key, known_hosts_files)
+        }
+        HostKeyVerdict::Reject => Err(RemoteError::new_ex(
+            RemoteErrorType::SslError,
+            format!(
+                "host key {} for {}:{} has been rejected",
+                host_key.sha256_fingerprint, host_key.host, host_key.port
+            ),
+        )),
+    }
+}
+
+/// Write `host_key` to the first known hosts file
+fn remember_host_key(
+    session: &Session,
+    host_key: &HostKey,
+    known_hosts_files: &[PathBuf],
+) -> RemoteResult<()> {
+    match known_hosts_files.first() {
+        Some(file) => add_known_host(session, file, host_key),
+        None => {
+            warn!("No known hosts file to write to; host key has not been saved");
+            Ok(())
+        }
+    }
+}
+
+/// Append the entry for `host_key` to the known hosts file at `file`
+fn add_known_host(session: &Session, file: &Path, host_key: &HostKey) -> RemoteResult<()> {
+    let entry_name = match host_key.port {
+        22 => host_key.host.to_string(),
+        port => format!("[{}]:{}", host_key.host, port),
+    };
+    // Use a new collection, so that only the new entry is serialized
+    let mut known_hosts = session
+        .known_hosts()
+        .map_err(|e| RemoteError::new_ex(RemoteErrorType::ProtocolError, e))?;
+    let line = known_hosts
+        .add(
+            &entry_name,
+            &host_key.key,
+            "",
+            KnownHostKeyFormat::from(host_key.key_type),
+        )
+        .and_then(|_| known_hosts.hosts())
+        .and_then(|hosts| match hosts.first() {
+            Some(entry) => known_hosts.write_string(entry, KnownHostFileKind::OpenSSH),
+            None => Ok(String::new()),
+        })
+        .map_err(|e| RemoteError::new_ex(RemoteErrorType::ProtocolError, e))?;
+    trace!("Writing known host entry to {}", file.display());
+    if let Some(parent) = file.parent() {
+        fs::create_dir_all(parent).map_err(|e| RemoteError::new_ex(RemoteErrorType::IoError, e))?;
+    }
+    OpenOptions::new()
+        .create(true)
+        .append(true)
+        .open(file)
+        .and_then(|mut f| f.write_all(line.as_bytes()))
+        .map_err(|e| {
+            RemoteError::new_ex(
+                RemoteErrorType::IoError,
+                format!("could not write known hosts file {}: {e}", file.display()),
+            )
+        })
+}
+
+#[cfg(test)]
+mod test {
+
+    use pretty_assertions::assert_eq;
+    use tempfile::TempDir;
+
+    use super::*;
+    use crate::mock::ssh as ssh_mock;
+
+    #[test]
+    fn should_make_host_key_fingerprints() {
+        let host_key = mock_host_key(10022);
+        assert_eq!(host_key.key_type.name(), "ssh-ed25519");
+        assert_eq!(
+            host_key.sha256_fingerprint.as_str(),
+            "SHA256:TFNO79pLOGPSUAov28dDGiNNrvEVUsvwOKm7F3Juvoo"
+        );
+        assert_eq!(
+            host_key.md5_fingerprint.as_str(),
+            "MD5:0e:74:93:5f:cc:c9:3d:93:f3:4b:24:6f:1d:a3:ee:f9"
+        );
+        assert!(host_key.matches_fingerprint("SHA256:TFNO79pLOGPSUAov28dDGiNNrvEVUsvwOKm7F3Juvoo"));
+        assert!(host_key.matches_fingerprint("MD5:0E:74:93:5F:CC:C9:3D:93:F3:4B:24:6F:1D:A3:EE:F9"));
+        assert!(!host_key.matches_fingerprint("SHA256:AAAA"));
+    }
+
+    #[test]
+    fn should_verify_pinned_fingerprints() {
+        let host_key = mock_host_key(10022);
+        let verifier = PinnedFingerprintVerifier::new(&[
+            "SHA256:AAAA",
+            "SHA256:TFNO79pLOGPSUAov28dDGiNNrvEVUsvwOKm7F3Juvoo",
+        ]);
+        assert_eq!(verifier.verify(&host_key), HostKeyVerdict::Accept);
+        let verifier = PinnedFingerprintVerifier::new(&["SHA256:AAAA"]);
+        assert_eq!(verifier.verify(&host_key), HostKeyVerdict::Reject);
+        assert_eq!(
+            AlwaysAcceptVerifier.verify(&host_key),
+            HostKeyVerdict::Accept
+        );
+    }
+
+    #[test]
+    fn should_accept_known_host_key() {
+        let session = Session::new().unwrap();
+        let known_hosts = ssh_mock::create_known_hosts("[127.0.0.1]:10022");
+        assert!(check_host_key(
+            &session,
+            &mock_host_key(10022),
+            HostKeyCheck::Strict,
+            &[known_hosts.path().to_path_buf()],
+            None,
+        )
+        .is_ok());
+    }
+
+    #[test]
+    fn should_reject_mismatching_host_key() {
+        let session = Session::new().unwrap();
+        let known_hosts = ssh_mock::create_known_hosts("[127.0.0.1]:10022");
+        let mut host_key = mock_host_key(10022);
+        let last = host_key.key.len() - 1;
+        host_key.key[last] ^= 0xff;
+        assert_eq!(
+            check_host_key(
+                &session,
+                &host_key,
+                HostKeyCheck::AcceptNew,
+                &[known_hosts.path().to_path_buf()],
+                Some(&AlwaysAcceptVerifier),
+            )
+            .unwrap_err()
+            .kind,
+            RemoteErrorType::SslError
+        );
+    }
+
+    #[test]
+    fn should_reject_unknown_host_key_when_strict() {
+        let session = Session::new().unwrap();
+        let known_hosts = ssh_mock::create_known_hosts("[127.0.0.1]:10022");
+        assert_eq!(
+            check_host_key(
+                &session,
+                &mock_host_key(10222),
+                HostKeyCheck::Strict,
+                &[known_hosts.path().to_path_buf()],
+                None,
+            )
+            .unwrap_err()
+            .kind,
+            RemoteErrorType::SslError
+        );
+    }
+
+    #[test]
+    fn should_add_unknown_host_key_when_accept_new() {
+        let session = Session::new().unwrap();
+        let dir = TempDir::new().unwrap();
+        let known_hosts = dir.path().join(".ssh").join("known_hosts");
+        for _ in 0..2 {
+            assert!(check_host_key(
+                &session,
+                &mock_host_key(10022),
+                HostKeyCheck::AcceptNew,
+                std::slice::from_ref(&known_hosts),
+                None,
+            )
+            .is_ok());
+        }
+        let content = fs::read_to_string(&known_hosts).unwrap();
+        assert_eq!(content.lines().count(), 1);
+        assert!(content.starts_with("[127.0.0.1]:10022 ssh-ed25519 "));
+        // must now be accepted with strict policy
+        assert!(check_host_key(
+            &session,
+            &mock_host_key(10022),
+            HostKeyCheck::Strict,
+            &[known_hosts],
+            None,
+        )
+        .is_ok());
+    }
+
+    #[test]
+    fn should_ask_verifier_for_unknown_host_key() {
+        let session = Session::new().unwrap();
+        let dir = TempDir::new().unwrap();
+        let known_hosts = dir.path().join("known_hosts");
+        // rejected
+        assert_eq!(
+            check_host_key(
+                &session,
+                &mock_host_key(10022),
+                HostKeyCheck::Strict,
+                std::slice::from_ref(&known_hosts),
+                Some(&PinnedFingerprintVerifier::new(&["SHA256:AAAA"])),
+            )
+            .unwrap_err()
+            .kind,
+            RemoteErrorType::SslError
+        );
+        // accepted, but not remembered
+        assert!(check_host_key(
+            &session,
+            &mock_host_key(10022),
+            HostKeyCheck::Strict,
+            std::slice::from_ref(&known_hosts),
+            Some(&AlwaysAcceptVerifier),
+        )
+        .is_ok());
+        assert!(!known_hosts.exists());
+        // accepted and remembered
+        assert!(check_host_key(
+            &session,
+            &mock_host_key(10022),
+            HostKeyCheck::Off,
+            std::slice::from_ref(&known_hosts),
+            Some(&RememberVerifier),
+        )
+        .is_ok());
+        assert!(check_host_key(
+            &session,
+            &mock_host_key(10022),
+            HostKeyCheck::Strict,
+            &[known_hosts],
+            None,
+        )
+        .is_ok());
+    }
+
+    struct RememberVerifier;
+
+    impl HostKeyVerifier for RememberVerifier {
+        fn verify(&self, _host_key: &HostKey) -> HostKeyVerdict {
+            HostKeyVerdict::AcceptAndRemember
+        }
+    }
+
+    fn mock_host_key(port: u16) -> HostKey {
+        HostKey::new(
+            "127.0.0.1",
+            port,
+            &ssh_mock::host_key(),
+            HostKeyType::Ed25519,
+            &ssh_mock::host_key_sha256(),
+            &ssh_mock::host_key_md5(),
+        )
+    }
+}
@@ -0,0 +1,83 @@
+//! ## Info
+//!
+//! information about an established connection
+
+use ssh2::{MethodType as SshMethodType, Session};
+
+use super::host_key::{self, HostKey};
+use super::AuthMethod;
+
+/// Algorithms negotiated for a direction of the connection
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct NegotiatedAlgorithms {
+    /// Cipher
+    pub cipher: Option<String>,
+    /// Message authentication code
+    pub mac: Option<String>,
+    /// Compression (`none` if disabled)
+    pub compression: Option<String>,
+}
+
+/// Information about an established connection (e.g. for audit logs):
+/// host key of the server, negotiated algorithms and authentication methods
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ConnectionInfo {
+    /// Host key presented by the server
+    pub host_key: Option<HostKey>,
+    /// Key exchange algorithm
+    pub kex: Option<String>,
+    /// Algorithms from the client to the server
+    pub client_to_server: NegotiatedAlgorithms,
+    /// Algorithms from the server to the client
+    pub server_to_client: NegotiatedAlgorithms,
+    /// Authentication methods which succeeded, in order; more than one if the server requires several methods
+    /// and reports their partial success, none if the server accepted the `none` method.
+    ///
+    /// libssh2 reports a partial success as a failure, so only the method completing the authentication may be listed
+    pub auth_methods: Vec<AuthMethod>,
+}
+
+impl ConnectionInfo {
+    /// Get the information of the connection to `host:port` from `session`, after the authentication
+    pub(crate) fn new(
+        session: &Session,
+        host: &str,
+        port: u16,
+        auth_methods: Vec<AuthMethod>,
+    ) -> Self {
+        let method = |method_type| session.methods(method_type).map(String::from);
+        Self {
+            host_key: host_key::session_host_key(session, host, port),
+            kex: method(SshMethodType::Kex),
+            client_to_server: NegotiatedAlgorithms {
+                cipher: method(SshMethodType::CryptCs),
+                mac: method(SshMethodType::MacCs),
+                compression: method(SshMethodType::CompCs),
+            },
+            server_to_client: NegotiatedAlgorithms {
+                cipher: method(SshMethodType::CryptSc),
+                mac: method(SshMethodType::MacSc),
+                compression: method(SshMethodType::CompSc),
+            },
+            auth_methods,
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+
+    use pretty_assertions::assert_eq;
+
+    use super::*;
+
+    #[test]
+    fn should_get_empty_info_without_handshake() {
+        let info = ConnectionInfo::new(&Session::new().unwrap(), "localhost", 22, Vec::new());
+        assert!(info.host_key.is_none());
+        assert!(info.kex.is_none());
+        assert_eq!(info.client_to_server, NegotiatedAlgorithms::default());
+        assert_eq!(info.server_to_client, NegotiatedAlgorithms::default());
+        assert!(info.auth_methods.is_empty());
+    }
+}
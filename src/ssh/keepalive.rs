@@ -0,0 +1,190 @@
+//! ## Keepalive
+//!
+//! keepalive messages to keep idle sessions open and to detect dead connections
+
+use std::io;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::mpsc::{self, RecvTimeoutError, Sender};
+use std::sync::Arc;
+use std::thread;
+use std::time::Duration;
+
+use ssh2::Session;
+
+use super::socket::{self, RawSocket};
+
+/// libssh2 error code of operations which would block
+const LIBSSH2_ERROR_EAGAIN: i32 = -37;
+
+/// Sends keepalive messages on a session every interval, until dropped.
+///
+/// The server answers to each keepalive; since libssh2 reads the answers only along with other data,
+/// a keepalive is answered if data from the server was received or read on the session socket before the next one is due.
+/// The session is considered dead once `count_max` keepalives in a row got no answer.
+///
+/// Keepalives never change the session timeout nor open channels, so they don't interfere with the operations running on the session
+pub struct Keepalive {
+    alive: Arc<AtomicBool>,
+    /// Whether data from the server has been read since the last keepalive was sent
+    received: Arc<AtomicBool>,
+    /// Dropping the sender stops the keepalive thread
+    _stop: Sender<()>,
+}
+
+impl Keepalive {
+    /// Start sending keepalive messages on `session`, which runs over `socket`, every `interval`.
+    ///
+    /// The keepalives must have been enabled on the session with `Session::set_keepalive`, asking the server to reply
+    pub fn start(
+        session: Session,
+        socket: RawSocket,
+        interval: Duration,
+        count_max: u32,
+    ) -> io::Result<Self> {
+        let alive = Arc::new(AtomicBool::new(true));
+        let received = Arc::new(AtomicBool::new(false));
+        let (stop, stopped) = mpsc::channel::<()>();
+        let thread_alive = alive.clone();
+        let thread_received = received.clone();
+        thread::Builder::new()
+            .name("ssh-keepalive".to_string())
+            .spawn(move || {
+                let mut missed = 0;
+                // bytes waiting on the socket when the last keepalive was sent
+                let mut pending_at_send: Option<usize> = None;
+                while let Err(RecvTimeoutError::Timeout) = stopped.recv_timeout(interval) {
+                    if let Some(before) = pending_at_send.take() {
+                        // data read or received since the keepalive was sent
+                        let answered = thread_received.swap(false, Ordering::Relaxed)
+                            || socket::pending(socket)
+                                .map(|pending| pending != before)
+                                .unwrap_or(false);
+                        if answered {
+                            trace!("Keepalive answered");
+                            missed = 0;
+                        } else {
+                            missed += 1;
+                            warn!("Keepalive got no answer ({missed}/{count_max})");
+                        }
+                    }
+                    if missed < count_max {
+                        thread_received.store(false, Ordering::Relaxed);
+                        let pending = socket::pending(socket).unwrap_or_default();
+                        match send(&session, interval) {
+                            Ok(true) => pending_at_send = Some(pending),
+                            Ok(false) => trace!("Keepalive not due yet"),
+                            Err(err) => {
+                                missed += 1;
+                                warn!("Could not send keepalive ({missed}/{count_max}): {err}");
+                            }
+                        }
+                    }
+                    if missed >= count_max {
+                        error!(
+                            "Server didn't answer to {count_max} keepalives; connection is dead"
+                        );
+                        thread_alive.store(false, Ordering::Relaxed);
+                        break;
+                    }
+                }
+                trace!("Keepalive stopped");
+            })?;
+        Ok(Self {
+            alive,
+            received,
+            _stop: stop,
+        })
+    }
+
+    /// Returns whether the connection is still alive
+    pub fn is_alive(&self) -> bool {
+        self.alive.load(Ordering::Relaxed)
+    }
+
+    /// Record that data from the server is being read from the session socket.
+    ///
+    /// Sessions which read data as soon as it is received, such as the ones of tunnels, must call it,
+    /// since the data is gone from the socket by the time the next keepalive is due
+    pub fn received(&self) {
+        self.received.store(true, Ordering::Relaxed);
+    }
+}
+
+/// Send a keepalive on `session`, which sends keepalives every `interval`; returns whether it was sent.
+///
+/// libssh2 doesn't send keepalives before the interval has elapsed and a non-blocking session may not be able to send it yet
+fn send(session: &Session, interval: Duration) -> Result<bool, ssh2::Error> {
+    match session.keepalive_send() {
+        // libssh2 returns the seconds until the next keepalive is due; the whole interval once it has been sent
+        Ok(next) => Ok(u64::from(next) == interval.as_secs()),
+        Err(err) if err.code() == ssh2::ErrorCode::Session(LIBSSH2_ERROR_EAGAIN) => Ok(false),
+        Err(err) => Err(err),
+    }
+}
+
+/// Send a keepalive on `session`, if keepalives are configured, and wait for the server to answer to a request, up to `timeout`.
+///
+/// The server is asked to open a channel, which is closed right away; the previous session timeout is restored afterwards.
+/// Only run by the `ping` methods of the clients, with the timeout given by the caller; keepalives sent in background never open channels
+pub fn ping(session: &Session, timeout: Duration) -> Result<(), ssh2::Error> {
+    let previous_timeout = session.timeout();
+    session.set_timeout(timeout.as_millis().clamp(1, u32::MAX as u128) as u32);
+    // opening a channel requires the server to answer
+    let result = session
+        .keepalive_send()
+        .and_then(|_| session.channel_session())
+        .and_then(|mut channel| channel.close());
+    session.set_timeout(previous_timeout);
+    result
+}
+
+#[cfg(test)]
+mod test {
+
+    use pretty_assertions::assert_eq;
+
+    use super::*;
+    use crate::ssh::socket::raw_socket;
+    use crate::ssh::tunnel::socket_pair;
+
+    /// Make a session over a socket whose peer has been closed; returns the session and its socket.
+    ///
+    /// The socket is given to libssh2 by the handshake, which fails;
+    /// without a socket, libssh2 would use file descriptor 0
+    fn disconnected_session() -> (Session, RawSocket) {
+        let mut session = Session::new().unwrap();
+        let (stream, peer) = socket_pair().unwrap();
+        drop(peer);
+        let socket = raw_socket(&stream);
+        session.set_tcp_stream(stream);
+        assert!(session.handshake().is_err());
+        (session, socket)
+    }
+
+    #[test]
+    fn should_detect_dead_connection() {
+        // keepalives can't be answered over a closed connection
+        let (session, socket) = disconnected_session();
+        session.set_keepalive(true, 1);
+        session.set_timeout(1234);
+        let keepalive =
+            Keepalive::start(session.clone(), socket, Duration::from_secs(1), 2).unwrap();
+        assert!(keepalive.is_alive());
+        let mut attempts = 0;
+        while keepalive.is_alive() && attempts < 500 {
+            thread::sleep(Duration::from_millis(10));
+            attempts += 1;
+        }
+        assert!(!keepalive.is_alive());
+        // keepalives don't change the timeout of the operations
+        assert_eq!(session.timeout(), 1234);
+    }
+
+    #[test]
+    fn should_not_ping_unconnected_session() {
+        let (session, _) = disconnected_session();
+        assert!(ping(&session, Duration::from_secs(1)).is_err());
+        // timeout is restored
+        assert_eq!(session.timeout(), 0);
+    }
+}
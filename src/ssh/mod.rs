@@ -3,28 +3,156 @@
 //! implements the file transfer for SSH based protocols: SFTP and SCP
 
 // -- ext
+use std::net::{IpAddr, SocketAddr};
 use std::path::{Path, PathBuf};
 use std::time::Duration;
 
+use base64::engine::general_purpose::STANDARD_NO_PAD;
+use base64::Engine as _;
+use remotefs::{RemoteError, RemoteErrorType, RemoteResult};
+use sha2::{Digest as _, Sha256};
+use zeroize::Zeroizing;
+
 // -- modules
+mod algorithms;
+mod auth;
 mod commons;
 mod config;
+mod connector;
+mod host_key;
+mod info;
+mod keepalive;
+mod proxy;
+mod reconnect;
+mod resolve;
+mod retry;
 mod scp;
+mod secret;
 mod sftp;
+mod shared;
+mod socket;
 mod stream;
+mod timeout;
+mod tunnel;
 // -- export
+pub use algorithms::AlgorithmProfile;
+pub use auth::{
+    AuthMethod, AuthPrompt, KeyboardInteractiveHandler, PassphraseProvider, PasswordProvider,
+};
+pub use connector::SshConnector;
+use connector::StreamConnector;
+pub use host_key::{
+    AlwaysAcceptVerifier, HostKey, HostKeyCheck, HostKeyType, HostKeyVerdict, HostKeyVerifier,
+    PinnedFingerprintVerifier,
+};
+pub use info::{ConnectionInfo, NegotiatedAlgorithms};
+pub use proxy::{Proxy, ProxyProtocol};
+pub use reconnect::{is_not_retried, NOT_RETRIED};
+pub use resolve::{OptionSource, Resolved, ResolvedAlgorithms, ResolvedConfig};
+pub use retry::{RetryPhase, RetryPolicy};
 pub use scp::ScpFs;
+pub use secret::Secret;
 pub use sftp::SftpFs;
+pub use shared::SharedSession;
 pub use ssh2::MethodType as SshMethodType;
 pub use ssh2_config::ParseRule;
 use stream::{SftpReadStream, SftpWriteStream};
+pub use timeout::{is_timeout, TIMED_OUT};
+pub use tunnel::SessionStream;
 
 // -- Ssh key storage
 
 /// This trait must be implemented in order to use ssh keys for authentication for sftp/scp.
-pub trait SshKeyStorage {
+///
+/// Implement either `resolve`, to provide key files, or `resolve_key`, to provide key files or in-memory keys.
+pub trait SshKeyStorage: Send {
     /// Return RSA key path from host and username
-    fn resolve(&self, host: &str, username: &str) -> Option<PathBuf>;
+    fn resolve(&self, host: &str, username: &str) -> Option<PathBuf> {
+        let _ = (host, username);
+        None
+    }
+
+    /// Return the private key to authenticate with from host and username.
+    ///
+    /// By default returns the key file provided by `resolve`
+    fn resolve_key(&self, host: &str, username: &str) -> Option<SshKey> {
+        self.resolve(host, username).map(SshKey::File)
+    }
+
+    /// Return the path of the OpenSSH certificate (e.g. `id_ed25519-cert.pub`) of the key resolved for host and username.
+    ///
+    /// By default no certificate is returned, so the `CertificateFile` of the ssh configuration
+    /// or the `-cert.pub` file next to the key is used
+    fn resolve_certificate(&self, host: &str, username: &str) -> Option<PathBuf> {
+        let _ = (host, username);
+        None
+    }
+}
+
+/// A private key to authenticate with
+#[derive(Clone, PartialEq, Eq)]
+pub enum SshKey {
+    /// Private key stored in a file
+    File(PathBuf),
+    /// Private key held in memory
+    Memory(SshKeyMaterial),
+}
+
+impl From<PathBuf> for SshKey {
+    fn from(p: PathBuf) -> Self {
+        SshKey::File(p)
+    }
+}
+
+impl From<SshKeyMaterial> for SshKey {
+    fn from(k: SshKeyMaterial) -> Self {
+        SshKey::Memory(k)
+    }
+}
+
+impl std::fmt::Debug for SshKey {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            SshKey::File(p) => f.debug_tuple("File").field(p).finish(),
+            SshKey::Memory(_) => f.write_str("Memory(..)"),
+        }
+    }
+}
+
+/// Private key material, in PEM or OpenSSH format, with an optional public key in OpenSSH format.
+///
+/// Key material is fed to libssh2 as is; the private key may be encrypted,
+/// in which case the key passphrase is used if set, otherwise the password.
+#[derive(Clone, PartialEq, Eq)]
+pub struct SshKeyMaterial {
+    pub(crate) private_key: Zeroizing<Vec<u8>>,
+    pub(crate) public_key: Option<Vec<u8>>,
+    pub(crate) passphrase: Option<Secret>,
+}
+
+impl SshKeyMaterial {
+    /// Instantiates a new `SshKeyMaterial` from the private key data
+    pub fn new(private_key: impl Into<Vec<u8>>) -> Self {
+        Self {
+            private_key: Zeroizing::new(private_key.into()),
+            public_key: None,
+            passphrase: None,
+        }
+    }
+
+    /// Set the public key data associated to the private key.
+    ///
+    /// An OpenSSH certificate (the content of a `-cert.pub` file) may be provided to authenticate with the certificate
+    pub fn public_key(mut self, public_key: impl Into<Vec<u8>>) -> Self {
+        self.public_key = Some(public_key.into());
+        self
+    }
+
+    /// Set the passphrase to decrypt the private key with
+    pub fn passphrase<S: AsRef<str>>(mut self, passphrase: S) -> Self {
+        self.passphrase = Some(Secret::from(passphrase.as_ref()));
+        self
+    }
 }
 
 // -- key method
@@ -44,11 +172,6 @@ impl KeyMethod {
             algos: algos.to_vec(),
         }
     }
-
-    /// Get preferred algos in ssh protocol syntax
-    pub(crate) fn prefs(&self) -> String {
-        self.algos.join(",")
-    }
 }
 
 // -- ssh options
@@ -60,6 +183,12 @@ pub enum SshAgentIdentity {
     All,
     /// Use a specific identity
     Pubkey(Vec<u8>),
+    /// Use the identity with the SHA256 fingerprint, in the same format used by OpenSSH (`SHA256:...`)
+    Fingerprint(String),
+    /// Use the identities with the comment (e.g. `user@host` or the key file path)
+    Comment(String),
+    /// Try the identities matching any of the candidates, in the order the agent lists them
+    OneOf(Vec<SshAgentIdentity>),
 }
 
 impl From<Vec<u8>> for SshAgentIdentity {
@@ -82,6 +211,49 @@ impl SshAgentIdentity {
         match self {
             SshAgentIdentity::All => true,
             SshAgentIdentity::Pubkey(v) => v == blob,
+            SshAgentIdentity::Fingerprint(fingerprint) => {
+                let fingerprint = fingerprint.strip_prefix("SHA256:").unwrap_or(fingerprint);
+                fingerprint.trim_end_matches('=')
+                    == STANDARD_NO_PAD.encode(Sha256::digest(blob)).as_str()
+            }
+            SshAgentIdentity::Comment(_) => false,
+            SshAgentIdentity::OneOf(identities) => {
+                identities.iter().any(|x| x.pubkey_matches(blob))
+            }
+        }
+    }
+
+    /// Check if the agent identity with public key `blob` and `comment` matches the identity
+    pub(crate) fn identity_matches(&self, blob: &[u8], comment: &str) -> bool {
+        match self {
+            SshAgentIdentity::Comment(v) => v == comment,
+            SshAgentIdentity::OneOf(identities) => {
+                identities.iter().any(|x| x.identity_matches(blob, comment))
+            }
+            _ => self.pubkey_matches(blob),
+        }
+    }
+}
+
+/// Address family of the addresses to connect to the server with
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum AddressFamily {
+    /// Any address family
+    #[default]
+    Any,
+    /// IPv4 only
+    Inet,
+    /// IPv6 only
+    Inet6,
+}
+
+impl AddressFamily {
+    /// Check if `address` belongs to the address family
+    pub(crate) fn matches(&self, address: &SocketAddr) -> bool {
+        match self {
+            AddressFamily::Any => true,
+            AddressFamily::Inet => address.is_ipv4(),
+            AddressFamily::Inet6 => address.is_ipv6(),
         }
     }
 }
@@ -107,7 +279,11 @@ pub struct SshOpts {
     /// Username to authenticate with
     username: Option<String>,
     /// Password to authenticate or to decrypt RSA key
-    password: Option<String>,
+    password: Option<Secret>,
+    /// Provider of the password, asked when no password is set
+    password_provider: Option<Box<dyn PasswordProvider>>,
+    /// Provider of the passphrases to decrypt private keys
+    passphrase_provider: Option<Box<dyn PassphraseProvider>>,
     /// Connection timeout (default 30 seconds)
     connection_timeout: Option<Duration>,
     /// SSH configuration file. If provided will be parsed on connect.
@@ -116,10 +292,48 @@ pub struct SshOpts {
     key_storage: Option<Box<dyn SshKeyStorage>>,
     /// Preferred key exchange methods.
     methods: Vec<KeyMethod>,
+    /// Profile of the preferred algorithms
+    algorithm_profile: Option<AlgorithmProfile>,
     /// Ssh config parser ruleset
     parse_rules: ParseRule,
     /// Ssh agent configuration for authentication
     ssh_agent_identity: Option<SshAgentIdentity>,
+    /// Socket of the ssh agent
+    identity_agent: Option<PathBuf>,
+    /// Host key checking policy
+    host_key_check: Option<HostKeyCheck>,
+    /// Known hosts file
+    known_hosts_file: Option<PathBuf>,
+    /// Host key verifier
+    host_key_verifier: Option<Box<dyn HostKeyVerifier>>,
+    /// Keyboard-interactive authentication handler
+    keyboard_interactive: Option<Box<dyn KeyboardInteractiveHandler>>,
+    /// Authentication methods to try, in order
+    auth_methods: Vec<AuthMethod>,
+    /// Jump hosts to connect through; if `None` they're read from `ProxyJump`
+    jump_hosts: Option<Vec<SshOpts>>,
+    /// Command to connect to the server through
+    proxy_command: Option<String>,
+    /// SOCKS5 or HTTP proxy to connect to the server through
+    proxy: Option<Proxy>,
+    /// Connector establishing the stream to run the session over
+    connector: Option<Box<dyn SshConnector>>,
+    /// Whether the connector provides a stream only once, so that failed connection attempts can't be retried
+    single_use_connector: bool,
+    /// Interval to send keepalive messages at
+    server_alive_interval: Option<Duration>,
+    /// Keepalive messages which may fail in a row before the connection is considered dead
+    server_alive_count_max: Option<u32>,
+    /// Whether to reconnect automatically when the connection is lost
+    auto_reconnect: bool,
+    /// Policy to retry failed connection attempts
+    retry_policy: RetryPolicy,
+    /// Timeout of the blocking calls on the session
+    operation_timeout: Option<Duration>,
+    /// Address family to connect with
+    address_family: Option<AddressFamily>,
+    /// Local address to connect from
+    bind_address: Option<IpAddr>,
 }
 
 impl SshOpts {
@@ -134,12 +348,33 @@ impl SshOpts {
             port: None,
             username: None,
             password: None,
+            password_provider: None,
+            passphrase_provider: None,
             connection_timeout: None,
             config_file: None,
             key_storage: None,
             methods: Vec::default(),
+            algorithm_profile: None,
             parse_rules: ParseRule::STRICT,
             ssh_agent_identity: None,
+            identity_agent: None,
+            host_key_check: None,
+            known_hosts_file: None,
+            host_key_verifier: None,
+            keyboard_interactive: None,
+            auth_methods: AuthMethod::DEFAULT_CHAIN.to_vec(),
+            jump_hosts: None,
+            proxy_command: None,
+            proxy: None,
+            connector: None,
+            single_use_connector: false,
+            server_alive_interval: None,
+            server_alive_count_max: None,
+            auto_reconnect: false,
+            retry_policy: RetryPolicy::default(),
+            operation_timeout: None,
+            address_family: None,
+            bind_address: None,
         }
     }
 
@@ -159,7 +394,23 @@ impl SshOpts {
 
     /// Set password to authenticate with
     pub fn password<S: AsRef<str>>(mut self, password: S) -> Self {
-        self.password = Some(password.as_ref().to_string());
+        self.password = Some(Secret::from(password.as_ref()));
+        self
+    }
+
+    /// Set the provider of the password to authenticate with.
+    ///
+    /// The provider is called only when the password method is attempted and no password is set
+    pub fn password_provider(mut self, provider: Box<dyn PasswordProvider>) -> Self {
+        self.password_provider = Some(provider);
+        self
+    }
+
+    /// Set the provider of the passphrases to decrypt private keys with.
+    ///
+    /// The provider is called only for encrypted keys. If not set, the password is used as passphrase
+    pub fn passphrase_provider(mut self, provider: Box<dyn PassphraseProvider>) -> Self {
+        self.passphrase_provider = Some(provider);
         self
     }
 
@@ -175,12 +426,35 @@ impl SshOpts {
     /// If `None` the ssh agent will be disabled
     ///
     /// If `Some(SshAgentIdentity::All)` all identities will be tried
-    /// Otherwise the provided public key will be used
+    /// Otherwise the identities matching the provided public key, fingerprint or comment will be used
     pub fn ssh_agent_identity(mut self, ssh_agent_identity: Option<SshAgentIdentity>) -> Self {
         self.ssh_agent_identity = ssh_agent_identity;
         self
     }
 
+    /// Set the socket of the ssh agent to authenticate with.
+    /// This option will override an eventual `IdentityAgent` specified for the current host in the ssh configuration.
+    ///
+    /// If neither is set, the agent at `SSH_AUTH_SOCK` is used
+    pub fn identity_agent<P: AsRef<Path>>(mut self, p: P) -> Self {
+        self.identity_agent = Some(p.as_ref().to_path_buf());
+        self
+    }
+
+    /// Request the ssh agent forwarding on the channels running commands.
+    ///
+    /// Agent forwarding is not supported: libssh2 refuses the `auth-agent@openssh.com` channels the server opens to reach the agent.
+    /// Requesting it returns an `UnsupportedFeature` error; `ForwardAgent` in the ssh configuration is ignored with a warning
+    pub fn forward_agent(self, forward: bool) -> RemoteResult<Self> {
+        match forward {
+            true => Err(RemoteError::new_ex(
+                RemoteErrorType::UnsupportedFeature,
+                "agent forwarding is not supported: libssh2 can't serve the agent channels opened by the server",
+            )),
+            false => Ok(self),
+        }
+    }
+
     /// Set SSH configuration file to read
     ///
     /// The supported options are:
@@ -195,23 +469,228 @@ impl SshOpts {
     /// - HostKeyAlgorithms
     /// - ConnectionAttempts
     /// - ConnectTimeout
+    /// - StrictHostKeyChecking
+    /// - UserKnownHostsFile
+    /// - IdentityFile
+    /// - IdentitiesOnly
+    /// - IdentityAgent
+    /// - CertificateFile
+    /// - ProxyJump
+    /// - ProxyCommand (if `ProxyJump` is set too, `ProxyJump` is used)
+    /// - ServerAliveInterval
+    /// - ServerAliveCountMax
+    /// - AddressFamily
+    /// - BindAddress
     pub fn config_file<P: AsRef<Path>>(mut self, p: P, rules: ParseRule) -> Self {
         self.config_file = Some(p.as_ref().to_path_buf());
         self.parse_rules = rules;
         self
     }
 
-    /// Set key storage to read RSA keys from
+    /// Set key storage to read RSA keys from.
+    ///
+    /// The key resolved by the storage is tried before the `IdentityFile`s of the ssh configuration
+    /// (or the default keys `~/.ssh/id_ed25519`, `~/.ssh/id_ecdsa` and `~/.ssh/id_rsa`)
     pub fn key_storage(mut self, storage: Box<dyn SshKeyStorage>) -> Self {
         self.key_storage = Some(storage);
         self
     }
 
-    /// Add key method to ssh options
+    /// Add key method to ssh options.
+    ///
+    /// The algorithms are checked against the algorithms supported by libssh2 on connect:
+    /// an unsupported algorithm makes the connection fail with `UnsupportedFeature`
     pub fn method(mut self, method: KeyMethod) -> Self {
         self.methods.push(method);
         self
     }
+
+    /// Set the profile of the preferred key exchange, host key, cipher and MAC algorithms.
+    /// This option will override the algorithms specified in the ssh configuration,
+    /// while the algorithms set with `method` override the profile for their method type.
+    ///
+    /// Algorithms of the profile which are not supported by libssh2 are left out
+    pub fn algorithm_profile(mut self, profile: AlgorithmProfile) -> Self {
+        self.algorithm_profile = Some(profile);
+        self
+    }
+
+    /// Set host key checking policy.
+    /// This option will override an eventual `StrictHostKeyChecking` specified for the current host in the ssh configuration.
+    ///
+    /// If neither is set, keys of unknown hosts are added to the known hosts file and changed keys are refused
+    /// (`HostKeyCheck::AcceptNew`), so that connecting to a new host keeps working.
+    /// Use `HostKeyCheck::Strict` to refuse unknown hosts, and `HostKeyCheck::Off` only for test or trusted networks
+    pub fn host_key_check(mut self, check: HostKeyCheck) -> Self {
+        self.host_key_check = Some(check);
+        self
+    }
+
+    /// Set known hosts file to verify host keys against.
+    /// This option will override an eventual `UserKnownHostsFile` specified for the current host in the ssh configuration.
+    ///
+    /// If neither is set, `~/.ssh/known_hosts` is used
+    pub fn known_hosts_file<P: AsRef<Path>>(mut self, p: P) -> Self {
+        self.known_hosts_file = Some(p.as_ref().to_path_buf());
+        self
+    }
+
+    /// Set host key verifier, to decide whether to trust the server host key.
+    ///
+    /// See `HostKeyVerifier` to know when the verifier is invoked.
+    pub fn host_key_verifier(mut self, verifier: Box<dyn HostKeyVerifier>) -> Self {
+        self.host_key_verifier = Some(verifier);
+        self
+    }
+
+    /// Set handler to answer the prompts of keyboard-interactive authentication.
+    ///
+    /// Keyboard-interactive authentication is attempted only if the handler is set
+    pub fn keyboard_interactive(mut self, handler: Box<dyn KeyboardInteractiveHandler>) -> Self {
+        self.keyboard_interactive = Some(handler);
+        self
+    }
+
+    /// Set the authentication methods to try, in order.
+    ///
+    /// Each method is attempted only if it is allowed by the server and configured
+    /// (e.g. `Password` requires a password or a password provider and `Agent` requires the ssh agent identity).
+    /// Each time the server changes the methods which can continue (e.g. after a partial success),
+    /// the methods are tried again from the first one, until the server accepts the authentication;
+    /// methods which already failed are skipped, unless they have just become allowed.
+    ///
+    /// Default: `Agent`, `PublicKey`, `Password`, `KeyboardInteractive`
+    pub fn auth_methods(mut self, methods: &[AuthMethod]) -> Self {
+        self.auth_methods = methods.to_vec();
+        self
+    }
+
+    /// Set the jump hosts (bastions) to connect through, in order.
+    /// This option will override an eventual `ProxyJump` specified for the current host in the ssh configuration.
+    ///
+    /// Each jump host is connected and authenticated with its own options;
+    /// the jump hosts of the jump hosts are not used, except for the first one.
+    /// An empty list disables `ProxyJump`.
+    pub fn jump_hosts(mut self, hosts: Vec<SshOpts>) -> Self {
+        self.jump_hosts = Some(hosts);
+        self
+    }
+
+    /// Set the command to connect to the server through (e.g. `nc %h %p`); the session runs over its stdin and stdout.
+    /// This option will override an eventual `ProxyCommand` and `ProxyJump` specified for the current host in the ssh configuration.
+    ///
+    /// The command is run with the system shell; `%h`, `%p`, `%r`, `%n` and `%%` are expanded.
+    /// As in OpenSSH, connecting fails with `BadAddress` if a host or user name to substitute contains shell metacharacters.
+    /// `none` disables `ProxyCommand`.
+    pub fn proxy_command<S: AsRef<str>>(mut self, command: S) -> Self {
+        self.proxy_command = Some(command.as_ref().to_string());
+        self
+    }
+
+    /// Set the SOCKS5 or HTTP proxy to establish the tcp connection with the server through.
+    ///
+    /// Connection attempts and timeout apply to the connection with the proxy and its handshake.
+    /// Jump hosts read from `ProxyJump` are connected through the proxy too
+    pub fn proxy(mut self, proxy: Proxy) -> Self {
+        self.proxy = Some(proxy);
+        self
+    }
+
+    /// Set the connector to establish the stream the session runs over, instead of connecting with tcp.
+    ///
+    /// Jump hosts, proxy command and proxy are not used when a connector is set
+    pub fn connector(mut self, connector: Box<dyn SshConnector>) -> Self {
+        self.connector = Some(connector);
+        self.single_use_connector = false;
+        self
+    }
+
+    /// Set the interval to send keepalive messages to the server at, to keep idle sessions open.
+    /// This option will override an eventual `ServerAliveInterval` specified for the current host in the ssh configuration.
+    ///
+    /// A zero interval disables keepalives, which are disabled by default
+    pub fn server_alive_interval(mut self, interval: Duration) -> Self {
+        self.server_alive_interval = Some(interval);
+        self
+    }
+
+    /// Set the number of keepalive messages which may fail in a row before the connection is considered dead.
+    /// This option will override an eventual `ServerAliveCountMax` specified for the current host in the ssh configuration.
+    ///
+    /// Default: 3
+    pub fn server_alive_count_max(mut self, count: u32) -> Self {
+        self.server_alive_count_max = Some(count);
+        self
+    }
+
+    /// Set whether to reconnect automatically when the connection is lost during an operation (default: false).
+    ///
+    /// After reconnecting, the working directory is restored and idempotent operations (e.g. `stat`, `list_dir` and `open`)
+    /// are run again; other operations fail with a `NOT_RETRIED` error (see `is_not_retried`), since they may have been performed.
+    /// Streams opened before the connection was lost are not recovered
+    pub fn auto_reconnect(mut self, reconnect: bool) -> Self {
+        self.auto_reconnect = reconnect;
+        self
+    }
+
+    /// Set the policy to retry failed connection attempts, up to `RetryPolicy::max_attempts` times or else `ConnectionAttempts` times.
+    ///
+    /// By default only tcp connection failures are retried, without delay.
+    /// Attempts over a stream set with `stream` are never retried, since the stream can be used only once
+    pub fn retry_policy(mut self, policy: RetryPolicy) -> Self {
+        self.retry_policy = policy;
+        self
+    }
+
+    /// Set the time to wait for the server during an operation (including the handshake and the authentication),
+    /// after which the operation fails with a `TIMED_OUT` error (see `is_timeout`).
+    /// The session may then be out of sync with the server, so the client is no longer connected and must connect again.
+    ///
+    /// A zero timeout disables it, which is disabled by default.
+    /// The timeout can be overridden for a single operation with `SftpFs::with_timeout` and `ScpFs::with_timeout`
+    pub fn operation_timeout(mut self, timeout: Duration) -> Self {
+        self.operation_timeout = Some(timeout);
+        self
+    }
+
+    /// Set the address family of the addresses to connect to the server with.
+    /// This option will override an eventual `AddressFamily` specified for the current host in the ssh configuration.
+    ///
+    /// With a proxy, the server addresses of the family are resolved locally and the proxy is asked to connect to them;
+    /// with `AddressFamily::Any` the proxy resolves the server host itself.
+    ///
+    /// Default: `AddressFamily::Any`
+    pub fn address_family(mut self, family: AddressFamily) -> Self {
+        self.address_family = Some(family);
+        self
+    }
+
+    /// Set the local address to connect from; only the server addresses of the same family are tried.
+    /// This option will override an eventual `BindAddress` specified for the current host in the ssh configuration.
+    ///
+    /// With a proxy, the bind address applies to the connection with the proxy, so only the proxy addresses of the same family are tried
+    pub fn bind_address(mut self, address: IpAddr) -> Self {
+        self.bind_address = Some(address);
+        self
+    }
+
+    /// Resolve the effective configuration to connect with, from these options and the ssh configuration file,
+    /// without connecting (like `ssh -G`).
+    ///
+    /// Each option is annotated with its source: an option set here, the configuration file or the default value
+    pub fn resolve(&self) -> RemoteResult<ResolvedConfig> {
+        config::Config::try_from(self).map(|config| ResolvedConfig::new(self, &config))
+    }
+
+    /// Set an already connected stream (e.g. a `TcpStream` or a `UnixStream`) to run the session over.
+    ///
+    /// The stream can be used only once: failed connection attempts are not retried,
+    /// and connecting again after a disconnection fails with `ConnectionError`
+    pub fn stream<S: SessionStream>(self, stream: S) -> Self {
+        let mut opts = self.connector(Box::new(StreamConnector::new(Box::new(stream))));
+        opts.single_use_connector = true;
+        opts
+    }
 }
 
 impl From<SshOpts> for SftpFs {
@@ -256,7 +735,10 @@ mod test {
     use pretty_assertions::assert_eq;
 
     use super::*;
-    use crate::mock::ssh::MockSshKeyStorage;
+    use crate::mock::ssh::{
+        MockInMemoryKeyStorage, MockKeyboardInteractiveHandler, MockPassphraseProvider,
+        MockPasswordProvider, MockSshKeyStorage,
+    };
 
     #[test]
     fn should_create_key_method() {
@@ -271,7 +753,7 @@ mod test {
             ],
         );
         assert_eq!(
-            key_method.prefs().as_str(),
+            key_method.algos.join(",").as_str(),
             "aes128-ctr,aes192-ctr,aes256-ctr,aes128-cbc,3des-cbc"
         );
     }
@@ -286,6 +768,53 @@ mod test {
         assert!(identity.pubkey_matches(b"hello"));
     }
 
+    #[test]
+    fn should_tell_whether_identity_matches() {
+        let blob = b"hello";
+        // echo -n hello | sha256sum | xxd -r -p | base64
+        let fingerprint = "SHA256:LPJNul+wow4m6DsqxbninhsWHlwfp0JecwQzYpOLmCQ";
+        assert!(SshAgentIdentity::Fingerprint(fingerprint.to_string()).pubkey_matches(blob));
+        assert!(SshAgentIdentity::Fingerprint(format!("{fingerprint}=")).pubkey_matches(blob));
+        assert!(SshAgentIdentity::Fingerprint(
+            fingerprint.trim_start_matches("SHA256:").to_string()
+        )
+        .pubkey_matches(blob));
+        assert!(!SshAgentIdentity::Fingerprint(fingerprint.to_string()).pubkey_matches(b"world"));
+        let identity = SshAgentIdentity::Comment("omar@pc".to_string());
+        assert!(identity.identity_matches(blob, "omar@pc"));
+        assert!(!identity.identity_matches(blob, "omar@laptop"));
+        let identity = SshAgentIdentity::OneOf(vec![
+            SshAgentIdentity::Comment("omar@pc".to_string()),
+            SshAgentIdentity::Pubkey(b"world".to_vec()),
+        ]);
+        assert!(identity.identity_matches(blob, "omar@pc"));
+        assert!(identity.identity_matches(b"world", ""));
+        assert!(!identity.identity_matches(blob, "omar@laptop"));
+        assert!(SshAgentIdentity::All.identity_matches(blob, ""));
+    }
+
+    #[test]
+    fn should_resolve_key_from_storage() {
+        let storage = MockSshKeyStorage::default();
+        assert!(matches!(
+            storage.resolve_key("sftp", "sftp"),
+            Some(SshKey::File(_))
+        ));
+        assert!(storage.resolve_key("sftp", "root").is_none());
+        let storage = MockInMemoryKeyStorage;
+        assert!(storage.resolve("sftp", "sftp").is_none());
+        assert!(matches!(
+            storage.resolve_key("sftp", "sftp"),
+            Some(SshKey::Memory(_))
+        ));
+    }
+
+    #[test]
+    fn should_not_print_key_material() {
+        let key = SshKey::from(SshKeyMaterial::new("secret").public_key("ssh-rsa AAAA"));
+        assert_eq!(format!("{key:?}").as_str(), "Memory(..)");
+    }
+
     #[test]
     fn should_initialize_ssh_opts() {
         let opts = SshOpts::new("localhost");
@@ -293,10 +822,31 @@ mod test {
         assert!(opts.port.is_none());
         assert!(opts.username.is_none());
         assert!(opts.password.is_none());
+        assert!(opts.password_provider.is_none());
+        assert!(opts.passphrase_provider.is_none());
         assert!(opts.connection_timeout.is_none());
         assert!(opts.config_file.is_none());
         assert!(opts.key_storage.is_none());
         assert!(opts.methods.is_empty());
+        assert!(opts.host_key_check.is_none());
+        assert!(opts.known_hosts_file.is_none());
+        assert!(opts.host_key_verifier.is_none());
+        assert!(opts.keyboard_interactive.is_none());
+        assert_eq!(opts.auth_methods, AuthMethod::DEFAULT_CHAIN.to_vec());
+        assert!(opts.jump_hosts.is_none());
+        assert!(opts.proxy_command.is_none());
+        assert!(opts.proxy.is_none());
+        assert!(opts.connector.is_none());
+        assert!(!opts.single_use_connector);
+        assert!(opts.server_alive_interval.is_none());
+        assert!(opts.server_alive_count_max.is_none());
+        assert!(!opts.auto_reconnect);
+        assert_eq!(opts.retry_policy, RetryPolicy::default());
+        assert!(opts.operation_timeout.is_none());
+        assert!(opts.address_family.is_none());
+        assert!(opts.bind_address.is_none());
+        assert!(opts.algorithm_profile.is_none());
+        assert!(opts.identity_agent.is_none());
     }
 
     #[test]
@@ -305,6 +855,8 @@ mod test {
             .port(22)
             .username("foobar")
             .password("qwerty123")
+            .password_provider(Box::new(MockPasswordProvider::new("qwerty123")))
+            .passphrase_provider(Box::new(MockPassphraseProvider::new("secret")))
             .connection_timeout(Duration::from_secs(10))
             .config_file(Path::new("/home/pippo/.ssh/config"), ParseRule::STRICT)
             .key_storage(Box::new(MockSshKeyStorage::default()))
@@ -317,11 +869,34 @@ mod test {
                     "aes128-cbc".to_string(),
                     "3des-cbc".to_string(),
                 ],
-            ));
+            ))
+            .host_key_check(HostKeyCheck::AcceptNew)
+            .known_hosts_file(Path::new("/home/pippo/.ssh/known_hosts"))
+            .host_key_verifier(Box::new(AlwaysAcceptVerifier))
+            .keyboard_interactive(Box::new(MockKeyboardInteractiveHandler::new(
+                "qwerty123",
+                "123456",
+            )))
+            .auth_methods(&[AuthMethod::PublicKey, AuthMethod::Password])
+            .jump_hosts(vec![SshOpts::new("bastion").username("omar")])
+            .proxy_command("nc %h %p")
+            .proxy(Proxy::socks5("127.0.0.1:1080").credentials("omar", "secret"))
+            .stream(tunnel::socket_pair().unwrap().0)
+            .server_alive_interval(Duration::from_secs(15))
+            .server_alive_count_max(5)
+            .auto_reconnect(true)
+            .retry_policy(RetryPolicy::default().phases(&[RetryPhase::Handshake]))
+            .operation_timeout(Duration::from_secs(20))
+            .address_family(AddressFamily::Inet6)
+            .bind_address(IpAddr::from([0, 0, 0, 0, 0, 0, 0, 1]))
+            .algorithm_profile(AlgorithmProfile::Compatible)
+            .identity_agent(Path::new("/run/user/1000/agent.sock"));
         assert_eq!(opts.host.as_str(), "localhost");
         assert_eq!(opts.port.unwrap(), 22);
         assert_eq!(opts.username.as_deref().unwrap(), "foobar");
-        assert_eq!(opts.password.as_deref().unwrap(), "qwerty123");
+        assert_eq!(opts.password.as_ref().unwrap().expose(), "qwerty123");
+        assert!(opts.password_provider.is_some());
+        assert!(opts.passphrase_provider.is_some());
         assert_eq!(opts.connection_timeout.unwrap(), Duration::from_secs(10));
         assert_eq!(
             opts.config_file.as_deref().unwrap(),
@@ -329,6 +904,55 @@ mod test {
         );
         assert!(opts.key_storage.is_some());
         assert_eq!(opts.methods.len(), 1);
+        assert_eq!(opts.host_key_check, Some(HostKeyCheck::AcceptNew));
+        assert_eq!(
+            opts.known_hosts_file.as_deref().unwrap(),
+            Path::new("/home/pippo/.ssh/known_hosts")
+        );
+        assert!(opts.host_key_verifier.is_some());
+        assert!(opts.keyboard_interactive.is_some());
+        assert_eq!(
+            opts.auth_methods,
+            vec![AuthMethod::PublicKey, AuthMethod::Password]
+        );
+        assert_eq!(opts.jump_hosts.as_ref().unwrap().len(), 1);
+        assert_eq!(opts.proxy_command.as_deref().unwrap(), "nc %h %p");
+        assert_eq!(
+            opts.proxy.as_ref().unwrap().protocol(),
+            ProxyProtocol::Socks5
+        );
+        assert_eq!(opts.proxy.as_ref().unwrap().address(), "127.0.0.1:1080");
+        assert!(opts.connector.is_some());
+        assert!(opts.single_use_connector);
+        assert_eq!(opts.server_alive_interval, Some(Duration::from_secs(15)));
+        assert_eq!(opts.server_alive_count_max, Some(5));
+        assert!(opts.auto_reconnect);
+        assert!(opts.retry_policy.retries(RetryPhase::Handshake));
+        assert!(!opts.retry_policy.retries(RetryPhase::Tcp));
+        assert_eq!(opts.operation_timeout, Some(Duration::from_secs(20)));
+        assert_eq!(opts.address_family, Some(AddressFamily::Inet6));
+        assert_eq!(
+            opts.bind_address,
+            Some(IpAddr::from([0, 0, 0, 0, 0, 0, 0, 1]))
+        );
+        assert_eq!(opts.algorithm_profile, Some(AlgorithmProfile::Compatible));
+        assert_eq!(
+            opts.identity_agent.as_deref().unwrap(),
+            Path::new("/run/user/1000/agent.sock")
+        );
+    }
+
+    #[test]
+    fn should_refuse_agent_forwarding() {
+        assert!(SshOpts::new("localhost").forward_agent(false).is_ok());
+        assert_eq!(
+            SshOpts::new("localhost")
+                .forward_agent(true)
+                .err()
+                .unwrap()
+                .kind,
+            RemoteErrorType::UnsupportedFeature
+        );
     }
 
     #[test]
@@ -0,0 +1,439 @@
+//! ## Proxy
+//!
+//! implements the handshake with SOCKS5 and HTTP proxies
+
+use std::fmt;
+use std::io::{self, Read, Write};
+use std::net::{IpAddr, TcpStream};
+use std::time::Duration;
+
+use base64::engine::general_purpose::STANDARD as BASE64;
+use base64::Engine as _;
+use zeroize::Zeroizing;
+
+use super::secret::Secret;
+
+/// Proxy protocol
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ProxyProtocol {
+    /// SOCKS5 proxy
+    Socks5,
+    /// HTTP proxy, using the `CONNECT` method
+    Http,
+}
+
+/// Proxy to establish the connection with the ssh server through
+#[derive(Clone, PartialEq, Eq)]
+pub struct Proxy {
+    pub(crate) protocol: ProxyProtocol,
+    pub(crate) address: String,
+    pub(crate) credentials: Option<(String, Secret)>,
+}
+
+impl Proxy {
+    /// Instantiates a new SOCKS5 proxy listening at `address` (`host:port`)
+    pub fn socks5<S: AsRef<str>>(address: S) -> Self {
+        Self::new(ProxyProtocol::Socks5, address)
+    }
+
+    /// Instantiates a new HTTP proxy listening at `address` (`host:port`)
+    pub fn http<S: AsRef<str>>(address: S) -> Self {
+        Self::new(ProxyProtocol::Http, address)
+    }
+
+    fn new<S: AsRef<str>>(protocol: ProxyProtocol, address: S) -> Self {
+        Self {
+            protocol,
+            address: address.as_ref().to_string(),
+            credentials: None,
+        }
+    }
+
+    /// Set the credentials to authenticate on the proxy with
+    pub fn credentials<U: AsRef<str>, P: AsRef<str>>(mut self, username: U, password: P) -> Self {
+        self.credentials = Some((
+            username.as_ref().to_string(),
+            Secret::from(password.as_ref()),
+        ));
+        self
+    }
+
+    /// Get proxy protocol
+    pub fn protocol(&self) -> ProxyProtocol {
+        self.protocol
+    }
+
+    /// Get proxy address
+    pub fn address(&self) -> &str {
+        &self.address
+    }
+
+    /// Ask the proxy connected on `stream` to connect to `host:port`.
+    ///
+    /// The handshake must complete within `timeout`, if not zero.
+    /// If the proxy rejects the credentials, an error of kind `PermissionDenied` is returned
+    pub(crate) fn handshake(
+        &self,
+        mut stream: TcpStream,
+        host: &str,
+        port: u16,
+        timeout: Duration,
+    ) -> io::Result<TcpStream> {
+        let timeout = (!timeout.is_zero()).then_some(timeout);
+        stream.set_read_timeout(timeout)?;
+        stream.set_write_timeout(timeout)?;
+        match self.protocol {
+            ProxyProtocol::Socks5 => self.socks5_handshake(&mut stream, host, port)?,
+            ProxyProtocol::Http => self.http_handshake(&mut stream, host, port)?,
+        }
+        stream.set_read_timeout(None)?;
+        stream.set_write_timeout(None)?;
+        Ok(stream)
+    }
+
+    /// Perform SOCKS5 handshake (RFC 1928), with username/password authentication (RFC 1929)
+    fn socks5_handshake<S: Read + Write>(
+        &self,
+        stream: &mut S,
+        host: &str,
+        port: u16,
+    ) -> io::Result<()> {
+        const VERSION: u8 = 0x05;
+        const NO_AUTH: u8 = 0x00;
+        const USERNAME_PASSWORD: u8 = 0x02;
+        const NO_ACCEPTABLE_METHODS: u8 = 0xff;
+        const CONNECT: u8 = 0x01;
+
+        // negotiate authentication method
+        match self.credentials {
+            Some(_) => stream.write_all(&[VERSION, 2, NO_AUTH, USERNAME_PASSWORD])?,
+            None => stream.write_all(&[VERSION, 1, NO_AUTH])?,
+        }
+        let mut reply = [0; 2];
+        stream.read_exact(&mut reply)?;
+        if reply[0] != VERSION {
+            return Err(proxy_error("invalid SOCKS5 reply"));
+        }
+        match (reply[1], self.credentials.as_ref()) {
+            (NO_AUTH, _) => {}
+            (USERNAME_PASSWORD, Some((username, password))) => {
+                let password = password.expose();
+                if username.len() > 255 || password.len() > 255 {
+                    return Err(proxy_error("SOCKS5 credentials are too long"));
+                }
+                let mut request =
+                    Zeroizing::new(Vec::with_capacity(3 + username.len() + password.len()));
+                request.extend_from_slice(&[0x01, username.len() as u8]);
+                request.extend_from_slice(username.as_bytes());
+                request.push(password.len() as u8);
+                request.extend_from_slice(password.as_bytes());
+                stream.write_all(&request)?;
+                stream.read_exact(&mut reply)?;
+                if reply[1] != 0x00 {
+                    return Err(io::Error::new(
+                        io::ErrorKind::PermissionDenied,
+                        "SOCKS5 proxy rejected the credentials",
+                    ));
+                }
+            }
+            (NO_ACCEPTABLE_METHODS, _) | (USERNAME_PASSWORD, None) => {
+                return Err(io::Error::new(
+                    io::ErrorKind::PermissionDenied,
+                    "SOCKS5 proxy requires authentication",
+                ))
+            }
+            (method, _) => {
+                return Err(proxy_error(&format!(
+                    "SOCKS5 proxy selected unsupported authentication method {method}"
+                )))
+            }
+        }
+        // connect
+        let mut request = vec![VERSION, CONNECT, 0x00];
+        match host.parse::<IpAddr>() {
+            Ok(IpAddr::V4(ip)) => {
+                request.push(0x01);
+                request.extend_from_slice(&ip.octets());
+            }
+            Ok(IpAddr::V6(ip)) => {
+                request.push(0x04);
+                request.extend_from_slice(&ip.octets());
+            }
+            Err(_) if host.len() <= 255 => {
+                request.push(0x03);
+                request.push(host.len() as u8);
+                request.extend_from_slice(host.as_bytes());
+            }
+            Err(_) => return Err(proxy_error("host name is too long for SOCKS5")),
+        }
+        request.extend_from_slice(&port.to_be_bytes());
+        stream.write_all(&request)?;
+        let mut reply = [0; 4];
+        stream.read_exact(&mut reply)?;
+        if reply[0] != VERSION {
+            return Err(proxy_error("invalid SOCKS5 reply"));
+        }
+        if reply[1] != 0x00 {
+            return Err(proxy_error(&format!(
+                "SOCKS5 proxy could not connect to {host}:{port}: {}",
+                socks5_reply_message(reply[1])
+            )));
+        }
+        // consume bound address and port
+        let address_len = match reply[3] {
+            0x01 => 4,
+            0x04 => 16,
+            0x03 => {
+                let mut len = [0; 1];
+                stream.read_exact(&mut len)?;
+                len[0] as usize
+            }
+            _ => return Err(proxy_error("invalid SOCKS5 bound address")),
+        };
+        let mut bound = vec![0; address_len + 2];
+        stream.read_exact(&mut bound)?;
+        Ok(())
+    }
+
+    /// Perform HTTP `CONNECT` handshake, with basic authentication
+    fn http_handshake<S: Read + Write>(
+        &self,
+        stream: &mut S,
+        host: &str,
+        port: u16,
+    ) -> io::Result<()> {
+        let authority = match host.parse::<IpAddr>() {
+            Ok(IpAddr::V6(_)) => format!("[{host}]:{port}"),
+            _ => format!("{host}:{port}"),
+        };
+        let mut request = format!("CONNECT {authority} HTTP/1.1\r\nHost: {authority}\r\n");
+        if let Some((username, password)) = self.credentials.as_ref() {
+            let credentials = Secret::new(format!("{username}:{}", password.expose()));
+            let encoded = Secret::new(BASE64.encode(credentials.expose()));
+            // reserve the whole header, so that the request is not reallocated leaving copies of the credentials
+            request.reserve(encoded.expose().len() + 32);
+            request.push_str("Proxy-Authorization: Basic ");
+            request.push_str(encoded.expose());
+            request.push_str("\r\n");
+        }
+        request.push_str("\r\n");
+        let request = Secret::new(request);
+        stream.write_all(request.expose().as_bytes())?;
+        // read response head byte by byte, not to consume the ssh banner
+        let mut response = Vec::new();
+        let mut byte = [0; 1];
+        while !response.ends_with(b"\r\n\r\n") {
+            if response.len() > 16 * 1024 {
+                return Err(proxy_error("HTTP proxy response is too long"));
+            }
+            stream.read_exact(&mut byte)?;
+            response.push(byte[0]);
+        }
+        let response = String::from_utf8_lossy(&response);
+        let status_line = response.lines().next().unwrap_or_default();
+        match status_line.split_whitespace().nth(1) {
+            Some(status) if status.starts_with('2') => Ok(()),
+            Some("407") => Err(io::Error::new(
+                io::ErrorKind::PermissionDenied,
+                format!("HTTP proxy requires authentication: {status_line}"),
+            )),
+            _ => Err(proxy_error(&format!(
+                "HTTP proxy could not connect to {authority}: {status_line}"
+            ))),
+        }
+    }
+}
+
+impl fmt::Debug for Proxy {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Proxy")
+            .field("protocol", &self.protocol)
+            .field("address", &self.address)
+            .field(
+                "credentials",
+                &self
+                    .credentials
+                    .as_ref()
+                    .map(|(username, _)| (username, "****")),
+            )
+            .finish()
+    }
+}
+
+fn proxy_error(message: &str) -> io::Error {
+    io::Error::other(message.to_string())
+}
+
+/// Describe SOCKS5 reply code
+fn socks5_reply_message(code: u8) -> &'static str {
+    match code {
+        0x01 => "general failure",
+        0x02 => "connection not allowed by ruleset",
+        0x03 => "network unreachable",
+        0x04 => "host unreachable",
+        0x05 => "connection refused",
+        0x06 => "TTL expired",
+        0x07 => "command not supported",
+        0x08 => "address type not supported",
+        _ => "unknown error",
+    }
+}
+
+#[cfg(test)]
+mod test {
+
+    use std::net::TcpListener;
+    use std::thread;
+
+    use pretty_assertions::assert_eq;
+
+    use super::*;
+
+    /// Run `server` on a local listener and return a stream connected to it
+    fn serve<F>(server: F) -> (TcpStream, thread::JoinHandle<()>)
+    where
+        F: FnOnce(TcpStream) + Send + 'static,
+    {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let address = listener.local_addr().unwrap();
+        let handle = thread::spawn(move || server(listener.accept().unwrap().0));
+        (TcpStream::connect(address).unwrap(), handle)
+    }
+
+    fn read_vec(stream: &mut TcpStream, len: usize) -> Vec<u8> {
+        let mut buf = vec![0; len];
+        stream.read_exact(&mut buf).unwrap();
+        buf
+    }
+
+    #[test]
+    fn should_connect_through_socks5_proxy() {
+        let (stream, server) = serve(|mut stream| {
+            assert_eq!(read_vec(&mut stream, 3), vec![5, 1, 0]);
+            stream.write_all(&[5, 0]).unwrap();
+            let mut request = vec![5, 1, 0, 3, 4];
+            request.extend_from_slice(b"sftp");
+            request.extend_from_slice(&22u16.to_be_bytes());
+            assert_eq!(read_vec(&mut stream, request.len()), request);
+            stream
+                .write_all(&[5, 0, 0, 1, 127, 0, 0, 1, 0, 22])
+                .unwrap();
+            stream.write_all(b"SSH-2.0-OpenSSH\r\n").unwrap();
+        });
+        let mut stream = Proxy::socks5("127.0.0.1:1080")
+            .handshake(stream, "sftp", 22, Duration::from_secs(5))
+            .unwrap();
+        server.join().unwrap();
+        let mut banner = String::new();
+        stream.read_to_string(&mut banner).unwrap();
+        assert_eq!(banner.as_str(), "SSH-2.0-OpenSSH\r\n");
+    }
+
+    #[test]
+    fn should_authenticate_on_socks5_proxy() {
+        let (stream, server) = serve(|mut stream| {
+            assert_eq!(read_vec(&mut stream, 4), vec![5, 2, 0, 2]);
+            stream.write_all(&[5, 2]).unwrap();
+            let mut request = vec![1, 4];
+            request.extend_from_slice(b"omar");
+            request.push(6);
+            request.extend_from_slice(b"secret");
+            assert_eq!(read_vec(&mut stream, request.len()), request);
+            stream.write_all(&[1, 0]).unwrap();
+            let mut request = vec![5, 1, 0, 1, 10, 0, 0, 1];
+            request.extend_from_slice(&2222u16.to_be_bytes());
+            assert_eq!(read_vec(&mut stream, request.len()), request);
+            stream.write_all(&[5, 0, 0, 3, 1, b'a', 0, 22]).unwrap();
+        });
+        assert!(Proxy::socks5("127.0.0.1:1080")
+            .credentials("omar", "secret")
+            .handshake(stream, "10.0.0.1", 2222, Duration::ZERO)
+            .is_ok());
+        server.join().unwrap();
+    }
+
+    #[test]
+    fn should_fail_socks5_handshake() {
+        // bad credentials
+        let (stream, server) = serve(|mut stream| {
+            read_vec(&mut stream, 4);
+            stream.write_all(&[5, 2]).unwrap();
+            read_vec(&mut stream, 13);
+            stream.write_all(&[1, 1]).unwrap();
+        });
+        let err = Proxy::socks5("127.0.0.1:1080")
+            .credentials("omar", "secret")
+            .handshake(stream, "sftp", 22, Duration::from_secs(5))
+            .err()
+            .unwrap();
+        assert_eq!(err.kind(), io::ErrorKind::PermissionDenied);
+        server.join().unwrap();
+        // connection refused
+        let (stream, server) = serve(|mut stream| {
+            read_vec(&mut stream, 3);
+            stream.write_all(&[5, 0]).unwrap();
+            read_vec(&mut stream, 11);
+            stream.write_all(&[5, 5, 0, 1, 0, 0, 0, 0, 0, 0]).unwrap();
+        });
+        let err = Proxy::socks5("127.0.0.1:1080")
+            .handshake(stream, "sftp", 22, Duration::from_secs(5))
+            .err()
+            .unwrap();
+        assert!(err.to_string().contains("connection refused"));
+        server.join().unwrap();
+    }
+
+    #[test]
+    fn should_connect_through_http_proxy() {
+        let (stream, server) = serve(|mut stream| {
+            let request = "CONNECT [::1]:22 HTTP/1.1\r\nHost: [::1]:22\r\nProxy-Authorization: Basic b21hcjpzZWNyZXQ=\r\n\r\n";
+            assert_eq!(
+                String::from_utf8(read_vec(&mut stream, request.len())).unwrap(),
+                request
+            );
+            stream
+                .write_all(b"HTTP/1.1 200 Connection established\r\n\r\nSSH-2.0-OpenSSH\r\n")
+                .unwrap();
+        });
+        let mut stream = Proxy::http("127.0.0.1:3128")
+            .credentials("omar", "secret")
+            .handshake(stream, "::1", 22, Duration::from_secs(5))
+            .unwrap();
+        server.join().unwrap();
+        let mut banner = String::new();
+        stream.read_to_string(&mut banner).unwrap();
+        assert_eq!(banner.as_str(), "SSH-2.0-OpenSSH\r\n");
+    }
+
+    #[test]
+    fn should_fail_http_handshake() {
+        let (stream, server) = serve(|mut stream| {
+            read_vec(&mut stream, 43);
+            stream
+                .write_all(b"HTTP/1.1 407 Proxy Authentication Required\r\n\r\n")
+                .unwrap();
+        });
+        let err = Proxy::http("127.0.0.1:3128")
+            .handshake(stream, "sftp", 22, Duration::from_secs(5))
+            .err()
+            .unwrap();
+        assert_eq!(err.kind(), io::ErrorKind::PermissionDenied);
+        server.join().unwrap();
+        let (stream, server) = serve(|mut stream| {
+            read_vec(&mut stream, 43);
+            stream.write_all(b"HTTP/1.1 403 Forbidden\r\n\r\n").unwrap();
+        });
+        let err = Proxy::http("127.0.0.1:3128")
+            .handshake(stream, "sftp", 22, Duration::from_secs(5))
+            .err()
+            .unwrap();
+        assert!(err.to_string().contains("403 Forbidden"));
+        server.join().unwrap();
+    }
+
+    #[test]
+    fn should_not_print_proxy_password() {
+        let proxy = Proxy::socks5("127.0.0.1:1080").credentials("omar", "secret");
+        assert!(!format!("{proxy:?}").contains("secret"));
+    }
+}
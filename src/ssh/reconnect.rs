@@ -0,0 +1,122 @@
+//! ## Reconnect
+//!
+//! detection of lost connections, to reconnect automatically
+
+use remotefs::{RemoteError, RemoteErrorType};
+use ssh2::{ErrorCode, Session};
+
+use super::keepalive::Keepalive;
+
+/// Message of the error returned by an operation which failed because the connection was lost;
+/// the client reconnected, but the operation was not retried since it may not be idempotent
+pub const NOT_RETRIED: &str = "reconnected, operation not retried";
+
+// libssh2 error codes of transport failures
+const LIBSSH2_ERROR_BANNER_RECV: i32 = -2;
+const LIBSSH2_ERROR_BANNER_SEND: i32 = -3;
+const LIBSSH2_ERROR_SOCKET_SEND: i32 = -7;
+const LIBSSH2_ERROR_SOCKET_DISCONNECT: i32 = -13;
+const LIBSSH2_ERROR_SOCKET_RECV: i32 = -43;
+
+/// Returns whether `err` has been returned by an operation which was not retried after reconnecting.
+///
+/// The operation may have been performed or not, so its outcome should be checked before running it again
+pub fn is_not_retried(err: &RemoteError) -> bool {
+    err.kind == RemoteErrorType::ConnectionError
+        && err
+            .msg
+            .as_deref()
+            .map(|x| x.starts_with(NOT_RETRIED))
+            .unwrap_or(false)
+}
+
+/// Make the error to return for an operation which failed with `err` and was not retried after reconnecting
+pub(crate) fn not_retried(err: RemoteError) -> RemoteError {
+    RemoteError::new_ex(
+        RemoteErrorType::ConnectionError,
+        format!("{NOT_RETRIED}: {err}"),
+    )
+}
+
+/// Returns whether the connection of `session` has been lost,
+/// because the last error is a transport failure or the keepalives failed
+pub(crate) fn connection_lost(session: Option<&Session>, keepalive: Option<&Keepalive>) -> bool {
+    let session = match session {
+        Some(session) => session,
+        None => return false,
+    };
+    if keepalive.map(|x| !x.is_alive()).unwrap_or(false) {
+        return true;
+    }
+    ssh2::Error::last_session_error(session)
+        .map(|err| is_transport_error(&err))
+        .unwrap_or(false)
+}
+
+/// Returns whether `err` is a failure of the transport the session runs over.
+///
+/// A socket timeout is not: it is caused by the operation timeout and reported as `TIMED_OUT`
+fn is_transport_error(err: &ssh2::Error) -> bool {
+    matches!(
+        err.code(),
+        ErrorCode::Session(
+            LIBSSH2_ERROR_BANNER_RECV
+                | LIBSSH2_ERROR_BANNER_SEND
+                | LIBSSH2_ERROR_SOCKET_SEND
+                | LIBSSH2_ERROR_SOCKET_DISCONNECT
+                | LIBSSH2_ERROR_SOCKET_RECV
+        )
+    )
+}
+
+#[cfg(test)]
+mod test {
+
+    use pretty_assertions::assert_eq;
+
+    use super::*;
+
+    #[test]
+    fn should_tell_whether_operation_was_not_retried() {
+        let err = not_retried(RemoteError::new(RemoteErrorType::FileCreateDenied));
+        assert_eq!(err.kind, RemoteErrorType::ConnectionError);
+        assert!(is_not_retried(&err));
+        assert!(!is_not_retried(&RemoteError::new(
+            RemoteErrorType::ConnectionError
+        )));
+        assert!(!is_not_retried(&RemoteError::new_ex(
+            RemoteErrorType::ConnectionError,
+            "connection timeout"
+        )));
+    }
+
+    #[test]
+    fn should_tell_whether_error_is_transport_failure() {
+        assert!(is_transport_error(&ssh2::Error::new(
+            ErrorCode::Session(LIBSSH2_ERROR_SOCKET_RECV),
+            "Failed getting banner"
+        )));
+        assert!(is_transport_error(&ssh2::Error::new(
+            ErrorCode::Session(LIBSSH2_ERROR_SOCKET_DISCONNECT),
+            "Socket disconnected"
+        )));
+        assert!(!is_transport_error(&ssh2::Error::new(
+            ErrorCode::SFTP(2),
+            "No such file"
+        )));
+        assert!(!is_transport_error(&ssh2::Error::new(
+            ErrorCode::Session(-18),
+            "Authentication failed"
+        )));
+        assert!(!is_transport_error(&ssh2::Error::new(
+            ErrorCode::Session(-30),
+            "Timed out waiting on socket"
+        )));
+    }
+
+    #[test]
+    fn should_not_report_connection_lost_without_session() {
+        assert!(!connection_lost(None, None));
+        assert!(!connection_lost(Some(&Session::new().unwrap()), None));
+    }
+}
@@ -0,0 +1,519 @@
+//! ## Resolve
+//!
+//! effective configuration of a connection, with the source of each option
+
+use std::fmt;
+use std::path::PathBuf;
+use std::time::Duration;
+
+use super::config::Config;
+use super::{MethodType, ProxyProtocol, SshOpts};
+
+/// Where a resolved option comes from
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum OptionSource {
+    /// Set in `SshOpts`
+    Option,
+    /// Read from the ssh configuration file
+    ConfigFile,
+    /// Default value
+    Default,
+}
+
+impl fmt::Display for OptionSource {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Option => write!(f, "option"),
+            Self::ConfigFile => write!(f, "config file"),
+            Self::Default => write!(f, "default"),
+        }
+    }
+}
+
+/// A resolved option, with its source
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Resolved<T> {
+    pub value: T,
+    pub source: OptionSource,
+}
+
+impl<T> Resolved<T> {
+    fn new(value: T, source: OptionSource) -> Self {
+        Self { value, source }
+    }
+
+    /// Resolve an option set in opts or in the configuration file, or else with its default value
+    fn from(value: T, option: bool, config_file: bool) -> Self {
+        let source = if option {
+            OptionSource::Option
+        } else if config_file {
+            OptionSource::ConfigFile
+        } else {
+            OptionSource::Default
+        };
+        Self::new(value, source)
+    }
+}
+
+/// Preferred algorithms for a method type; if empty, the libssh2 defaults are used
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ResolvedAlgorithms {
+    pub method_type: MethodType,
+    pub algorithms: Resolved<Vec<String>>,
+}
+
+/// Effective configuration to connect with, as resolved from `SshOpts` and the ssh configuration file
+/// (like `ssh -G`); see `SshOpts::resolve`.
+///
+/// Its `Display` implementation prints an option per line, with its source
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ResolvedConfig {
+    /// Host as provided to `SshOpts`
+    pub host: String,
+    /// Host to connect to (`HostName`)
+    pub host_name: Resolved<String>,
+    pub port: Resolved<u16>,
+    /// Address to connect to, as `host:port`
+    pub address: String,
+    pub username: Resolved<String>,
+    pub connection_timeout: Resolved<Duration>,
+    pub connection_attempts: Resolved<usize>,
+    /// Timeout of the operations; `None` if disabled
+    pub operation_timeout: Resolved<Option<Duration>>,
+    /// Interval to send keepalives at; `None` if disabled
+    pub server_alive_interval: Resolved<Option<Duration>>,
+    pub server_alive_count_max: Resolved<u32>,
+    /// Preferred algorithms, for each method type
+    pub algorithms: Vec<ResolvedAlgorithms>,
+    pub identity_files: Resolved<Vec<PathBuf>>,
+    pub certificate_file: Resolved<Option<PathBuf>>,
+    /// Jump hosts, as `[user@]host[:port]`
+    pub proxy_jump: Resolved<Vec<String>>,
+    pub proxy_command: Resolved<Option<String>>,
+    /// Proxy, as `protocol://address`
+    pub proxy: Resolved<Option<String>>,
+}
+
+impl ResolvedConfig {
+    /// Annotate the configuration resolved from `opts` with the source of each option
+    pub(crate) fn new(opts: &SshOpts, config: &Config) -> Self {
+        let params = &config.params;
+        let directives = &config.directives;
+        Self {
+            host: config.host.clone(),
+            host_name: Resolved::from(
+                config.resolved_host.clone(),
+                false,
+                params.host_name.is_some(),
+            ),
+            port: Resolved::from(config.port, opts.port.is_some(), params.port.is_some()),
+            address: config.address.clone(),
+            username: Resolved::from(
+                config.username.clone(),
+                opts.username.is_some(),
+                params.user.is_some(),
+            ),
+            connection_timeout: Resolved::from(
+                config.connection_timeout,
+                opts.connection_timeout.is_some(),
+                params.connect_timeout.is_some(),
+            ),
+            connection_attempts: Resolved::from(
+                config.connection_attempts,
+                opts.retry_policy.attempts().is_some(),
+                params.connection_attempts.is_some(),
+            ),
+            operation_timeout: Resolved::from(
+                opts.operation_timeout.filter(|x| !x.is_zero()),
+                opts.operation_timeout.is_some(),
+                false,
+            ),
+            server_alive_interval: Resolved::from(
+                config.server_alive_interval,
+                opts.server_alive_interval.is_some(),
+                params.server_alive_interval.is_some(),
+            ),
+            server_alive_count_max: Resolved::from(
+                config.server_alive_count_max,
+                opts.server_alive_count_max.is_some(),
+                directives.contains_key("serveralivecountmax"),
+            ),
+            algorithms: Self::resolve_algorithms(opts, config),
+            identity_files: Resolved::from(
+                config.identity_files.clone(),
+                false,
+                params.identity_file.is_some(),
+            ),
+            certificate_file: Resolved::from(
+                config.certificate_file.clone(),
+                false,
+                params.certificate_file.is_some(),
+            ),
+            proxy_jump: match opts.jump_hosts.as_deref() {
+                Some(jump_hosts) => Resolved::new(
+                    jump_hosts.iter().map(Self::jump_host).collect(),
+                    OptionSource::Option,
+                ),
+                None => Resolved::from(
+                    config
+                        .proxy_jump
+                        .iter()
+                        .map(|x| {
+                            let username = x.username.as_deref().map(|x| format!("{x}@"));
+                            let port = x.port.map(|x| format!(":{x}"));
+                            Self::address(username.as_deref(), &x.host, port.as_deref())
+                        })
+                        .collect(),
+                    opts.proxy_command.is_some(),
+                    directives.contains_key("proxyjump"),
+                ),
+            },
+            proxy_command: Resolved::from(
+                config.proxy_command.clone(),
+                opts.proxy_command.is_some(),
+                directives.contains_key("proxycommand"),
+            ),
+            proxy: Resolved::from(
+                opts.proxy.as_ref().map(|x| {
+                    let protocol = match x.protocol() {
+                        ProxyProtocol::Socks5 => "socks5",
+                        ProxyProtocol::Http => "http",
+                    };
+                    format!("{protocol}://{}", x.address())
+                }),
+                opts.proxy.is_some(),
+                false,
+            ),
+        }
+    }
+
+    /// Resolve the preferred algorithms of each method type; key methods override the algorithm profile,
+    /// which overrides the configuration file
+    pub(crate) fn resolve_algorithms(opts: &SshOpts, config: &Config) -> Vec<ResolvedAlgorithms> {
+        let params = &config.params;
+        [
+            (MethodType::Kex, params.kex_algorithms.as_deref()),
+            (MethodType::HostKey, params.host_key_algorithms.as_deref()),
+            (MethodType::CryptClientServer, params.ciphers.as_deref()),
+            (MethodType::CryptServerClient, params.ciphers.as_deref()),
+            (MethodType::MacClientServer, params.mac.as_deref()),
+            (MethodType::MacServerClient, params.mac.as_deref()),
+        ]
+        .into_iter()
+        .map(|(method_type, config_algos)| {
+            let algorithms = match opts
+                .methods
+                .iter()
+                .rev()
+                .find(|x| x.method_type == method_type)
+            {
+                Some(method) => Resolved::new(method.algos.clone(), OptionSource::Option),
+                None => match (opts.algorithm_profile, config_algos) {
+                    (Some(profile), _) => Resolved::new(
+                        profile
+                            .algorithms(method_type)
+                            .iter()
+                            .map(|x| x.to_string())
+                            .collect(),
+                        OptionSource::Option,
+                    ),
+                    (None, Some(algos)) => Resolved::new(algos.to_vec(), OptionSource::ConfigFile),
+                    (None, None) => Resolved::new(Vec::new(), OptionSource::Default),
+                },
+            };
+            ResolvedAlgorithms {
+                method_type,
+                algorithms,
+            }
+        })
+        .collect()
+    }
+
+    /// Format a jump host from opts as `[user@]host[:port]`
+    fn jump_host(opts: &SshOpts) -> String {
+        let username = opts.username.as_deref().map(|x| format!("{x}@"));
+        let port = opts.port.map(|x| format!(":{x}"));
+        Self::address(username.as_deref(), &opts.host, port.as_deref())
+    }
+
+    fn address(username: Option<&str>, host: &str, port: Option<&str>) -> String {
+        let host = match host.contains(':') && port.is_some() {
+            true => format!("[{host}]"),
+            false => host.to_string(),
+        };
+        format!(
+            "{}{host}{}",
+            username.unwrap_or_default(),
+            port.unwrap_or_default()
+        )
+    }
+}
+
+impl fmt::Display for ResolvedConfig {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        fn line<T: fmt::Display>(
+            f: &mut fmt::Formatter<'_>,
+            key: &str,
+            value: T,
+            source: OptionSource,
+        ) -> fmt::Result {
+            writeln!(f, "{key} {value} ({source})")
+        }
+        fn list<T: fmt::Display>(values: impl Iterator<Item = T>) -> String {
+            let values: Vec<String> = values.map(|x| x.to_string()).collect();
+            match values.is_empty() {
+                true => "none".to_string(),
+                false => values.join(","),
+            }
+        }
+        fn secs(value: Option<Duration>) -> String {
+            value
+                .map(|x| x.as_secs().to_string())
+                .unwrap_or_else(|| "none".to_string())
+        }
+
+        writeln!(f, "host {}", self.host)?;
+        line(f, "hostname", &self.host_name.value, self.host_name.source)?;
+        line(f, "port", self.port.value, self.port.source)?;
+        writeln!(f, "address {}", self.address)?;
+        line(f, "user", &self.username.value, self.username.source)?;
+        line(
+            f,
+            "connecttimeout",
+            self.connection_timeout.value.as_secs(),
+            self.connection_timeout.source,
+        )?;
+        line(
+            f,
+            "connectionattempts",
+            self.connection_attempts.value,
+            self.connection_attempts.source,
+        )?;
+        line(
+            f,
+            "operationtimeout",
+            secs(self.operation_timeout.value),
+            self.operation_timeout.source,
+        )?;
+        line(
+            f,
+            "serveraliveinterval",
+            secs(self.server_alive_interval.value),
+            self.server_alive_interval.source,
+        )?;
+        line(
+            f,
+            "serveralivecountmax",
+            self.server_alive_count_max.value,
+            self.server_alive_count_max.source,
+        )?;
+        for algos in self.algorithms.iter() {
+            let key = match algos.method_type {
+                MethodType::Kex => "kexalgorithms",
+                MethodType::HostKey => "hostkeyalgorithms",
+                MethodType::CryptClientServer => "ciphers-client-server",
+                MethodType::CryptServerClient => "ciphers-server-client",
+                MethodType::MacClientServer => "macs-client-server",
+                MethodType::MacServerClient => "macs-server-client",
+            };
+            let value = match algos.algorithms.value.is_empty() {
+                true => "libssh2-default".to_string(),
+                false => algos.algorithms.value.join(","),
+            };
+            line(f, key, value, algos.algorithms.source)?;
+        }
+        line(
+            f,
+            "identityfile",
+            list(self.identity_files.value.iter().map(|x| x.display())),
+            self.identity_files.source,
+        )?;
+        line(
+            f,
+            "certificatefile",
+            list(self.certificate_file.value.iter().map(|x| x.display())),
+            self.certificate_file.source,
+        )?;
+        line(
+            f,
+            "proxyjump",
+            list(self.proxy_jump.value.iter()),
+            self.proxy_jump.source,
+        )?;
+        line(
+            f,
+            "proxycommand",
+            self.proxy_command.value.as_deref().unwrap_or("none"),
+            self.proxy_command.source,
+        )?;
+        line(
+            f,
+            "proxy",
+            self.proxy.value.as_deref().unwrap_or("none"),
+            self.proxy.source,
+        )
+    }
+}
+
+#[cfg(test)]
+mod test {
+
+    use pretty_assertions::assert_eq;
+    use ssh2_config::ParseRule;
+
+    use super::*;
+    use crate::mock::ssh as ssh_mock;
+    use crate::{AlgorithmProfile, KeyMethod, Proxy};
+
+    #[test]
+    fn should_resolve_config_with_sources() {
+        let config_file = ssh_mock::create_ssh_config();
+        let resolved = SshOpts::new("sftp")
+            .config_file(config_file.path(), ParseRule::STRICT)
+            .port(2222)
+            .method(KeyMethod::new(
+                MethodType::MacClientServer,
+                &["hmac-sha2-256".to_string()],
+            ))
+            .proxy(Proxy::socks5("127.0.0.1:1080").credentials("omar", "secret"))
+            .resolve()
+            .unwrap();
+        assert_eq!(resolved.host.as_str(), "sftp");
+        assert_eq!(
+            resolved.host_name,
+            Resolved::new("127.0.0.1".to_string(), OptionSource::ConfigFile)
+        );
+        assert_eq!(resolved.port, Resolved::new(2222, OptionSource::Option));
+        assert_eq!(resolved.address.as_str(), "127.0.0.1:2222");
+        assert_eq!(
+            resolved.username,
+            Resolved::new("sftp".to_string(), OptionSource::ConfigFile)
+        );
+        assert_eq!(
+            resolved.connection_timeout,
+            Resolved::new(Duration::from_secs(60), OptionSource::ConfigFile)
+        );
+        assert_eq!(
+            resolved.connection_attempts,
+            Resolved::new(3, OptionSource::ConfigFile)
+        );
+        assert_eq!(
+            resolved.operation_timeout,
+            Resolved::new(None, OptionSource::Default)
+        );
+        let algorithms = |method_type| {
+            resolved
+                .algorithms
+                .iter()
+                .find(|x| x.method_type == method_type)
+                .unwrap()
+                .algorithms
+                .clone()
+        };
+        assert_eq!(
+            algorithms(MethodType::MacClientServer),
+            Resolved::new(vec!["hmac-sha2-256".to_string()], OptionSource::Option)
+        );
+        assert_eq!(
+            algorithms(MethodType::MacServerClient).source,
+            OptionSource::ConfigFile
+        );
+        assert_eq!(
+            algorithms(MethodType::HostKey),
+            Resolved::new(Vec::new(), OptionSource::Default)
+        );
+        assert_eq!(resolved.identity_files.source, OptionSource::ConfigFile);
+        assert_eq!(resolved.proxy_jump.source, OptionSource::Default);
+        assert_eq!(
+            resolved.proxy,
+            Resolved::new(
+                Some("socks5://127.0.0.1:1080".to_string()),
+                OptionSource::Option
+            )
+        );
+        // credentials are not displayed
+        let output = resolved.to_string();
+        assert!(output.contains("hostname 127.0.0.1 (config file)\n"));
+        assert!(output.contains("port 2222 (option)\n"));
+        assert!(output.contains("hostkeyalgorithms libssh2-default (default)\n"));
+        assert!(!output.contains("secret"));
+    }
+
+    #[test]
+    fn should_resolve_algorithm_profile() {
+        let config_file = ssh_mock::create_ssh_config();
+        let resolved = SshOpts::new("sftp")
+            .config_file(config_file.path(), ParseRule::STRICT)
+            .algorithm_profile(AlgorithmProfile::Modern)
+            .method(KeyMethod::new(
+                MethodType::MacClientServer,
+                &["hmac-sha2-256".to_string()],
+            ))
+            .resolve()
+            .unwrap();
+        let algorithms = |method_type| {
+            resolved
+                .algorithms
+                .iter()
+                .find(|x| x.method_type == method_type)
+                .unwrap()
+                .algorithms
+                .clone()
+        };
+        // key methods override the profile, which overrides the configuration file
+        assert_eq!(
+            algorithms(MethodType::MacClientServer).value,
+            vec!["hmac-sha2-256".to_string()]
+        );
+        let mac = algorithms(MethodType::MacServerClient);
+        assert_eq!(mac.source, OptionSource::Option);
+        assert_eq!(
+            mac.value,
+            AlgorithmProfile::Modern.algorithms(MethodType::MacServerClient)
+        );
+        assert_eq!(
+            algorithms(MethodType::HostKey).value,
+            AlgorithmProfile::Modern.algorithms(MethodType::HostKey)
+        );
+    }
+
+    #[test]
+    fn should_resolve_proxy_jump_with_sources() {
+        let config_file = ssh_mock::create_ssh_config();
+        let resolved = SshOpts::new("internal")
+            .config_file(config_file.path(), ParseRule::STRICT)
+            .resolve()
+            .unwrap();
+        assert_eq!(
+            resolved.proxy_jump,
+            Resolved::new(
+                vec!["sftp".to_string(), "omar@10.0.0.1:2222".to_string()],
+                OptionSource::ConfigFile
+            )
+        );
+        let resolved = SshOpts::new("internal")
+            .config_file(config_file.path(), ParseRule::STRICT)
+            .jump_hosts(vec![SshOpts::new("::1").port(2222).username("omar")])
+            .resolve()
+            .unwrap();
+        assert_eq!(
+            resolved.proxy_jump,
+            Resolved::new(vec!["omar@[::1]:2222".to_string()], OptionSource::Option)
+        );
+        // defaults
+        let resolved = SshOpts::new("localhost").resolve().unwrap();
+        assert_eq!(
+            resolved.host_name,
+            Resolved::new("localhost".to_string(), OptionSource::Default)
+        );
+        assert_eq!(resolved.port, Resolved::new(22, OptionSource::Default));
+        assert_eq!(
+            resolved.connection_attempts,
+            Resolved::new(1, OptionSource::Default)
+        );
+        assert_eq!(
+            resolved.proxy_command,
+            Resolved::new(None, OptionSource::Default)
+        );
+    }
+}
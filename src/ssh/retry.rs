@@ -0,0 +1,162 @@
+//! ## Retry
+//!
+//! retry policy of connection attempts
+
+use std::collections::hash_map::RandomState;
+use std::fmt;
+use std::hash::{BuildHasher, Hasher};
+use std::time::Duration;
+
+/// A phase of the connection to the server, whose failures may be retried
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum RetryPhase {
+    /// Establishment of the tcp connection (or of the stream through the proxy, the proxy command or the jump hosts)
+    Tcp,
+    /// Exchange of the protocol banners; e.g. the server closes connections beyond sshd `MaxStartups`
+    Banner,
+    /// Key exchange, or a disconnection during the authentication
+    Handshake,
+}
+
+impl fmt::Display for RetryPhase {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Tcp => write!(f, "tcp connection"),
+            Self::Banner => write!(f, "banner exchange"),
+            Self::Handshake => write!(f, "handshake"),
+        }
+    }
+}
+
+/// Policy to retry connection attempts; the number of attempts is set by `max_attempts`, or else by `ConnectionAttempts`.
+///
+/// The delay before the n-th retry is `base * 2^(n-1)`, up to `cap`, reduced by a random fraction up to `jitter`.
+///
+/// By default, only tcp connection failures are retried, without delay
+#[derive(Debug, Clone, PartialEq)]
+pub struct RetryPolicy {
+    base: Duration,
+    cap: Duration,
+    jitter: f64,
+    phases: Vec<RetryPhase>,
+    max_attempts: Option<u32>,
+}
+
+impl Default for RetryPolicy {
+    fn default() -> Self {
+        Self {
+            base: Duration::ZERO,
+            cap: Duration::ZERO,
+            jitter: 0.0,
+            phases: vec![RetryPhase::Tcp],
+            max_attempts: None,
+        }
+    }
+}
+
+impl RetryPolicy {
+    /// Set the delay before the first retry and the maximum delay
+    pub fn backoff(mut self, base: Duration, cap: Duration) -> Self {
+        self.base = base;
+        self.cap = cap;
+        self
+    }
+
+    /// Set the maximum fraction (between `0` and `1`) of the delay which is randomly removed,
+    /// so that clients don't retry all at the same time
+    pub fn jitter(mut self, jitter: f64) -> Self {
+        self.jitter = jitter.clamp(0.0, 1.0);
+        self
+    }
+
+    /// Set the phases of the connection whose failures are retried
+    pub fn phases(mut self, phases: &[RetryPhase]) -> Self {
+        self.phases = phases.to_vec();
+        self
+    }
+
+    /// Set the maximum number of connection attempts (at least 1), which overrides `ConnectionAttempts`
+    pub fn max_attempts(mut self, attempts: u32) -> Self {
+        self.max_attempts = Some(attempts.max(1));
+        self
+    }
+
+    /// Returns whether failures during `phase` are retried
+    pub fn retries(&self, phase: RetryPhase) -> bool {
+        self.phases.contains(&phase)
+    }
+
+    /// Get the maximum number of connection attempts, if set
+    pub(crate) fn attempts(&self) -> Option<u32> {
+        self.max_attempts
+    }
+
+    /// Get the delay to wait before the retry which follows the failed `attempt` (starting from 1)
+    pub(crate) fn delay(&self, attempt: u32) -> Duration {
+        let exp = attempt.saturating_sub(1).min(31);
+        let delay = self.base.saturating_mul(1 << exp).min(self.cap);
+        if self.jitter == 0.0 {
+            return delay;
+        }
+        delay.mul_f64(1.0 - self.jitter * random_fraction())
+    }
+}
+
+/// Get a random number in `[0, 1)`
+fn random_fraction() -> f64 {
+    let random = RandomState::new().build_hasher().finish();
+    (random >> 11) as f64 / (1u64 << 53) as f64
+}
+
+#[cfg(test)]
+mod test {
+
+    use pretty_assertions::assert_eq;
+
+    use super::*;
+
+    #[test]
+    fn should_retry_only_tcp_by_default() {
+        let policy = RetryPolicy::default();
+        assert!(policy.retries(RetryPhase::Tcp));
+        assert!(!policy.retries(RetryPhase::Banner));
+        assert!(!policy.retries(RetryPhase::Handshake));
+        assert_eq!(policy.delay(1), Duration::ZERO);
+        assert_eq!(policy.delay(5), Duration::ZERO);
+        assert_eq!(policy.attempts(), None);
+    }
+
+    #[test]
+    fn should_set_max_attempts() {
+        assert_eq!(RetryPolicy::default().max_attempts(5).attempts(), Some(5));
+        assert_eq!(RetryPolicy::default().max_attempts(0).attempts(), Some(1));
+    }
+
+    #[test]
+    fn should_back_off_exponentially() {
+        let policy = RetryPolicy::default()
+            .backoff(Duration::from_millis(100), Duration::from_secs(1))
+            .phases(&[RetryPhase::Banner, RetryPhase::Handshake]);
+        assert!(!policy.retries(RetryPhase::Tcp));
+        assert!(policy.retries(RetryPhase::Banner));
+        assert_eq!(policy.delay(1), Duration::from_millis(100));
+        assert_eq!(policy.delay(2), Duration::from_millis(200));
+        assert_eq!(policy.delay(3), Duration::from_millis(400));
+        assert_eq!(policy.delay(4), Duration::from_millis(800));
+        assert_eq!(policy.delay(5), Duration::from_secs(1));
+        assert_eq!(policy.delay(100), Duration::from_secs(1));
+    }
+
+    #[test]
+    fn should_apply_jitter() {
+        let policy = RetryPolicy::default()
+            .backoff(Duration::from_secs(1), Duration::from_secs(10))
+            .jitter(0.5);
+        for _ in 0..100 {
+            let delay = policy.delay(1);
+            assert!(delay > Duration::from_millis(500));
+            assert!(delay <= Duration::from_secs(1));
+        }
+        assert_eq!(RetryPolicy::default().jitter(3.0).jitter, 1.0);
+    }
+}
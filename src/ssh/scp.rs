@@ -16,7 +16,10 @@ use remotefs::File;
 // -- export
 pub use ssh2::Session as SshSession;
 
-use super::{commons, SshOpts};
+use super::keepalive::Keepalive;
+use super::reconnect;
+use super::timeout::{self, TimeoutGuard};
+use super::{commons, ConnectionInfo, SshOpts};
 use crate::utils::{fmt as fmt_utils, parser as parser_utils, path as path_utils};
 
 /// NOTE: about this damn regex <https://stackoverflow.com/questions/32480890/is-there-a-regex-to-parse-the-values-from-an-ftp-directory-listing>
@@ -28,7 +31,12 @@ static LS_RE: Lazy<Regex> = lazy_regex!(
 pub struct ScpFs {
     session: Option<SshSession>,
     wrkdir: PathBuf,
-    opts: SshOpts,
+    /// Options to connect with; `None` if the client has been created from a session
+    opts: Option<SshOpts>,
+    /// Keepalive of the session, if keepalives are enabled
+    keepalive: Option<Keepalive>,
+    /// Information about the connection, while connected
+    info: Option<ConnectionInfo>,
 }
 
 impl ScpFs {
@@ -37,15 +45,90 @@ impl ScpFs {
         Self {
             session: None,
             wrkdir: PathBuf::from("/"),
-            opts,
+            opts: Some(opts),
+            keepalive: None,
+            info: None,
         }
     }
 
+    /// Creates a new `ScpFs` from an authenticated session, which is then used in blocking mode.
+    ///
+    /// The session can be shared with other clients (e.g. a `SftpFs`) by providing a clone of it.
+    /// The working directory is resolved with `pwd`.
+    ///
+    /// The client is already connected; `disconnect` releases the session without closing it,
+    /// and `connect` can't establish a new session once the session has been released
+    pub fn from_session(mut session: SshSession) -> RemoteResult<Self> {
+        if !session.authenticated() {
+            return Err(RemoteError::new_ex(
+                RemoteErrorType::AuthenticationFailed,
+                "session is not authenticated",
+            ));
+        }
+        session.set_blocking(true);
+        debug!("Getting working directory...");
+        let wrkdir = commons::perform_shell_cmd(&mut session, "pwd")
+            .map(|x| PathBuf::from(x.as_str().trim()))?;
+        Ok(Self {
+            session: Some(session),
+            wrkdir,
+            opts: None,
+            keepalive: None,
+            info: None,
+        })
+    }
+
     /// Get a reference to current `session` value.
     pub fn session(&mut self) -> Option<&mut SshSession> {
         self.session.as_mut()
     }
 
+    /// Get the information about the connection (host key of the server, negotiated algorithms
+    /// and authentication methods), while connected.
+    ///
+    /// `None` if the client has been created from a session, unless created by a `SharedSession`
+    pub fn connection_info(&self) -> Option<&ConnectionInfo> {
+        self.info.as_ref()
+    }
+
+    /// Set the information about the connection of the session the client has been created from
+    pub(crate) fn with_connection_info(mut self, info: Option<ConnectionInfo>) -> Self {
+        self.info = info;
+        self
+    }
+
+    /// Check whether the server answers, waiting up to `timeout`.
+    ///
+    /// If the server doesn't answer, the session is released and the client is no longer connected
+    pub fn ping(&mut self, timeout: Duration) -> RemoteResult<()> {
+        let session = self
+            .session
+            .as_ref()
+            .ok_or_else(|| RemoteError::new(RemoteErrorType::NotConnected))?;
+        if let Err(err) = commons::ping(session, timeout) {
+            self.session = None;
+            self.keepalive = None;
+            self.info = None;
+            return Err(err);
+        }
+        Ok(())
+    }
+
+    /// Run `op` on the client with the operation timeout set to `timeout` (zero disables it),
+    /// e.g. to allow a longer time for a large transfer; the previous timeout is restored afterwards.
+    ///
+    /// The timeout applies to the whole session, thus to other clients sharing it, while `op` runs
+    pub fn with_timeout<T, F>(&mut self, timeout: Duration, op: F) -> RemoteResult<T>
+    where
+        F: FnOnce(&mut Self) -> RemoteResult<T>,
+    {
+        let _guard = self
+            .session
+            .clone()
+            .map(|session| TimeoutGuard::new(session, timeout));
+        op(self)
+    }
+
     // -- private
 
     /// Check connection status
@@ -57,6 +140,78 @@ impl ScpFs {
         }
     }
 
+    /// Run the idempotent operation `op`; if it fails because the connection was lost
+    /// and auto reconnect is enabled, reconnect and run it again.
+    /// A failure caused by the operation timeout is reported as a `TIMED_OUT` error
+    fn with_retry<T, F>(&mut self, mut op: F) -> RemoteResult<T>
+    where
+        F: FnMut(&mut Self) -> RemoteResult<T>,
+    {
+        let result = match op(self) {
+            Err(err) if self.connection_lost() => {
+                self.reconnect(&err)?;
+                op(self)
+            }
+            result => result,
+        };
+        result.map_err(|err| self.timed_out(err))
+    }
+
+    /// Run the operation `op`; if it fails because the connection was lost and auto reconnect is enabled,
+    /// reconnect and return a `NOT_RETRIED` error, since the operation may not be idempotent.
+    /// A failure caused by the operation timeout is reported as a `TIMED_OUT` error
+    fn with_reconnect<T, F>(&mut self, op: F) -> RemoteResult<T>
+    where
+        F: FnOnce(&mut Self) -> RemoteResult<T>,
+    {
+        match op(self) {
+            Err(err) if self.connection_lost() => {
+                self.reconnect(&err)?;
+                Err(reconnect::not_retried(err))
+            }
+            result => result.map_err(|err| self.timed_out(err)),
+        }
+    }
+
+    /// Report the failure `err` as a `TIMED_OUT` error if it was caused by the operation timeout.
+    ///
+    /// The session may then be out of sync with the server, thus it is released and the client is no longer connected
+    fn timed_out(&mut self, err: RemoteError) -> RemoteError {
+        let err = timeout::timed_out(self.session.as_ref(), err);
+        if timeout::is_timeout(&err) {
+            warn!("Releasing the session after the operation timed out");
+            self.session = None;
+            self.keepalive = None;
+            self.info = None;
+        }
+        err
+    }
+
+    /// Returns whether auto reconnect is enabled and the connection has been lost
+    fn connection_lost(&self) -> bool {
+        self.opts
+            .as_ref()
+            .map(|x| x.auto_reconnect)
+            .unwrap_or(false)
+            && reconnect::connection_lost(self.session.as_ref(), self.keepalive.as_ref())
+    }
+
+    /// Connect again after the connection was lost with `err`, restoring the working directory
+    fn reconnect(&mut self, err: &RemoteError) -> RemoteResult<()> {
+        warn!("Connection lost ({err}); reconnecting...");
+        let wrkdir = self.wrkdir.clone();
+        self.session = None;
+        self.keepalive = None;
+        self.info = None;
+        self.connect()?;
+        self.wrkdir = wrkdir;
+        info!(
+            "Reconnected; working directory restored to {}",
+            self.wrkdir.display()
+        );
+        Ok(())
+    }
+
     /// Parse a line of `ls -l` output and tokenize the output into a `FsFile`
     fn parse_ls_output(&self, path: &Path, line: &str) -> Result<File, ()> {
         // Prepare list regex
@@ -118,15 +273,9 @@ impl ScpFs {
                     Err(_) => SystemTime::UNIX_EPOCH,
                 };
                 // Get uid
-                let uid: Option<u32> = match metadata.get(4).unwrap().as_str().parse::<u32>() {
-                    Ok(uid) => Some(uid),
-                    Err(_) => None,
-                };
+                let uid: Option<u32> = metadata.get(4).unwrap().as_str().parse::<u32>().ok();
                 // Get gid
-                let gid: Option<u32> = match metadata.get(5).unwrap().as_str().parse::<u32>() {
-                    Ok(gid) => Some(gid),
-                    Err(_) => None,
-                };
+                let gid: Option<u32> = metadata.get(5).unwrap().as_str().parse::<u32>().ok();
                 // Get filesize
                 let size = metadata
                     .get(6)
@@ -217,59 +366,10 @@ impl ScpFs {
     }
 }
 
-impl RemoteFs for ScpFs {
-    fn connect(&mut self) -> RemoteResult<Welcome> {
-        debug!("Initializing SFTP connection...");
-        let mut session = commons::connect(&self.opts)?;
-        // Get banner
-        let banner: Option<String> = session.banner().map(String::from);
-        debug!(
-            "Connection established: {}",
-            banner.as_deref().unwrap_or("")
-        );
-        // Get working directory
-        debug!("Getting working directory...");
-        self.wrkdir = commons::perform_shell_cmd(&mut session, "pwd")
-            .map(|x| PathBuf::from(x.as_str().trim()))?;
-        // Set session
-        self.session = Some(session);
-        info!(
-            "Connection established; working directory: {}",
-            self.wrkdir.display()
-        );
-        Ok(Welcome::default().banner(banner))
-    }
-
-    fn disconnect(&mut self) -> RemoteResult<()> {
-        debug!("Disconnecting from remote...");
-        if let Some(session) = self.session.as_ref() {
-            // Disconnect (greet server with 'Mandi' as they do in Friuli)
-            match session.disconnect(None, "Mandi!", None) {
-                Ok(_) => {
-                    // Set session and sftp to none
-                    self.session = None;
-                    Ok(())
-                }
-                Err(err) => Err(RemoteError::new_ex(RemoteErrorType::ConnectionError, err)),
-            }
-        } else {
-            Err(RemoteError::new(RemoteErrorType::NotConnected))
-        }
-    }
-
-    fn is_connected(&mut self) -> bool {
-        self.session
-            .as_ref()
-            .map(|x| x.authenticated())
-            .unwrap_or(false)
-    }
-
-    fn pwd(&mut self) -> RemoteResult<PathBuf> {
-        self.check_connection()?;
-        Ok(self.wrkdir.clone())
-    }
+impl ScpFs {
+    // -- operations, run once by the `RemoteFs` methods with the reconnect policy
 
-    fn change_dir(&mut self, dir: &Path) -> RemoteResult<PathBuf> {
+    fn do_change_dir(&mut self, dir: &Path) -> RemoteResult<PathBuf> {
         self.check_connection()?;
         let dir = path_utils::absolutize(self.wrkdir.as_path(), dir);
         debug!("Changing working directory to {}", dir.display());
@@ -299,12 +399,12 @@ impl RemoteFs for ScpFs {
         }
     }
 
-    fn list_dir(&mut self, path: &Path) -> RemoteResult<Vec<File>> {
+    fn do_list_dir(&mut self, path: &Path) -> RemoteResult<Vec<File>> {
         self.check_connection()?;
         let path = path_utils::absolutize(self.wrkdir.as_path(), path);
         debug!("Getting file entries in {}", path.display());
         // check if exists
-        if !self.exists(path.as_path()).ok().unwrap_or(false) {
+        if !self.do_exists(path.as_path()).ok().unwrap_or(false) {
             return Err(RemoteError::new(RemoteErrorType::NoSuchFileOrDirectory));
         }
         match commons::perform_shell_cmd(
@@ -333,7 +433,7 @@ impl RemoteFs for ScpFs {
         }
     }
 
-    fn stat(&mut self, path: &Path) -> RemoteResult<File> {
+    fn do_stat(&mut self, path: &Path) -> RemoteResult<File> {
         self.check_connection()?;
         let path = path_utils::absolutize(self.wrkdir.as_path(), path);
         debug!("Stat {}", path.display());
@@ -363,7 +463,7 @@ impl RemoteFs for ScpFs {
         }
     }
 
-    fn exists(&mut self, path: &Path) -> RemoteResult<bool> {
+    fn do_exists(&mut self, path: &Path) -> RemoteResult<bool> {
         self.check_connection()?;
         let path = path_utils::absolutize(self.wrkdir.as_path(), path);
         match commons::perform_shell_cmd_with_rc(
@@ -376,11 +476,11 @@ impl RemoteFs for ScpFs {
         }
     }
 
-    fn setstat(&mut self, path: &Path, metadata: Metadata) -> RemoteResult<()> {
+    fn do_setstat(&mut self, path: &Path, metadata: Metadata) -> RemoteResult<()> {
         self.check_connection()?;
         let path = path_utils::absolutize(self.wrkdir.as_path(), path);
         debug!("Setting attributes for {}", path.display());
-        if !self.exists(path.as_path()).ok().unwrap_or(false) {
+        if !self.do_exists(path.as_path()).ok().unwrap_or(false) {
             return Err(RemoteError::new(RemoteErrorType::NoSuchFileOrDirectory));
         }
         // set mode with chmod
@@ -417,10 +517,10 @@ impl RemoteFs for ScpFs {
         Ok(())
     }
 
-    fn remove_file(&mut self, path: &Path) -> RemoteResult<()> {
+    fn do_remove_file(&mut self, path: &Path) -> RemoteResult<()> {
         self.check_connection()?;
         let path = path_utils::absolutize(self.wrkdir.as_path(), path);
-        if !self.exists(path.as_path()).ok().unwrap_or(false) {
+        if !self.do_exists(path.as_path()).ok().unwrap_or(false) {
             return Err(RemoteError::new(RemoteErrorType::NoSuchFileOrDirectory));
         }
         debug!("Removing file {}", path.display());
@@ -434,10 +534,10 @@ impl RemoteFs for ScpFs {
         }
     }
 
-    fn remove_dir(&mut self, path: &Path) -> RemoteResult<()> {
+    fn do_remove_dir(&mut self, path: &Path) -> RemoteResult<()> {
         self.check_connection()?;
         let path = path_utils::absolutize(self.wrkdir.as_path(), path);
-        if !self.exists(path.as_path()).ok().unwrap_or(false) {
+        if !self.do_exists(path.as_path()).ok().unwrap_or(false) {
             return Err(RemoteError::new(RemoteErrorType::NoSuchFileOrDirectory));
         }
         debug!("Removing directory {}", path.display());
@@ -451,10 +551,10 @@ impl RemoteFs for ScpFs {
         }
     }
 
-    fn remove_dir_all(&mut self, path: &Path) -> RemoteResult<()> {
+    fn do_remove_dir_all(&mut self, path: &Path) -> RemoteResult<()> {
         self.check_connection()?;
         let path = path_utils::absolutize(self.wrkdir.as_path(), path);
-        if !self.exists(path.as_path()).ok().unwrap_or(false) {
+        if !self.do_exists(path.as_path()).ok().unwrap_or(false) {
             return Err(RemoteError::new(RemoteErrorType::NoSuchFileOrDirectory));
         }
         debug!("Removing directory {} recursively", path.display());
@@ -468,10 +568,10 @@ impl RemoteFs for ScpFs {
         }
     }
 
-    fn create_dir(&mut self, path: &Path, mode: UnixPex) -> RemoteResult<()> {
+    fn do_create_dir(&mut self, path: &Path, mode: UnixPex) -> RemoteResult<()> {
         self.check_connection()?;
         let path = path_utils::absolutize(self.wrkdir.as_path(), path);
-        if self.exists(path.as_path()).ok().unwrap_or(false) {
+        if self.do_exists(path.as_path()).ok().unwrap_or(false) {
             return Err(RemoteError::new(RemoteErrorType::DirectoryAlreadyExists));
         }
         let mode = format!("{:o}", u32::from(mode));
@@ -490,7 +590,7 @@ impl RemoteFs for ScpFs {
         }
     }
 
-    fn symlink(&mut self, path: &Path, target: &Path) -> RemoteResult<()> {
+    fn do_symlink(&mut self, path: &Path, target: &Path) -> RemoteResult<()> {
         self.check_connection()?;
         let path = path_utils::absolutize(self.wrkdir.as_path(), path);
         debug!(
@@ -498,10 +598,10 @@ impl RemoteFs for ScpFs {
             path.display(),
             target.display()
         );
-        if !self.exists(target).ok().unwrap_or(false) {
+        if !self.do_exists(target).ok().unwrap_or(false) {
             return Err(RemoteError::new(RemoteErrorType::NoSuchFileOrDirectory));
         }
-        if self.exists(path.as_path()).ok().unwrap_or(false) {
+        if self.do_exists(path.as_path()).ok().unwrap_or(false) {
             return Err(RemoteError::new(RemoteErrorType::FileCreateDenied));
         }
         match commons::perform_shell_cmd_with_rc(
@@ -514,11 +614,11 @@ impl RemoteFs for ScpFs {
         }
     }
 
-    fn copy(&mut self, src: &Path, dest: &Path) -> RemoteResult<()> {
+    fn do_copy(&mut self, src: &Path, dest: &Path) -> RemoteResult<()> {
         self.check_connection()?;
         let src = path_utils::absolutize(self.wrkdir.as_path(), src);
         // check if file exists
-        if !self.exists(src.as_path()).ok().unwrap_or(false) {
+        if !self.do_exists(src.as_path()).ok().unwrap_or(false) {
             return Err(RemoteError::new(RemoteErrorType::NoSuchFileOrDirectory));
         }
         let dest = path_utils::absolutize(self.wrkdir.as_path(), dest);
@@ -540,11 +640,11 @@ impl RemoteFs for ScpFs {
         }
     }
 
-    fn mov(&mut self, src: &Path, dest: &Path) -> RemoteResult<()> {
+    fn do_mov(&mut self, src: &Path, dest: &Path) -> RemoteResult<()> {
         self.check_connection()?;
         let src = path_utils::absolutize(self.wrkdir.as_path(), src);
         // check if file exists
-        if !self.exists(src.as_path()).ok().unwrap_or(false) {
+        if !self.do_exists(src.as_path()).ok().unwrap_or(false) {
             return Err(RemoteError::new(RemoteErrorType::NoSuchFileOrDirectory));
         }
         let dest = path_utils::absolutize(self.wrkdir.as_path(), dest);
@@ -563,7 +663,7 @@ impl RemoteFs for ScpFs {
         }
     }
 
-    fn exec(&mut self, cmd: &str) -> RemoteResult<(u32, String)> {
+    fn do_exec(&mut self, cmd: &str) -> RemoteResult<(u32, String)> {
         self.check_connection()?;
         debug!(r#"Executing command "{}""#, cmd);
         commons::perform_shell_cmd_at_with_rc(
@@ -573,11 +673,7 @@ impl RemoteFs for ScpFs {
         )
     }
 
-    fn append(&mut self, _path: &Path, _metadata: &Metadata) -> RemoteResult<WriteStream> {
-        Err(RemoteError::new(RemoteErrorType::UnsupportedFeature))
-    }
-
-    fn create(&mut self, path: &Path, metadata: &Metadata) -> RemoteResult<WriteStream> {
+    fn do_create(&mut self, path: &Path, metadata: &Metadata) -> RemoteResult<WriteStream> {
         self.check_connection()?;
         let path = path_utils::absolutize(self.wrkdir.as_path(), path);
         debug!("Creating file {}", path.display());
@@ -619,12 +715,12 @@ impl RemoteFs for ScpFs {
         }
     }
 
-    fn open(&mut self, path: &Path) -> RemoteResult<ReadStream> {
+    fn do_open(&mut self, path: &Path) -> RemoteResult<ReadStream> {
         self.check_connection()?;
         let path = path_utils::absolutize(self.wrkdir.as_path(), path);
         debug!("Opening file {} for read", path.display());
         // check if file exists
-        if !self.exists(path.as_path()).ok().unwrap_or(false) {
+        if !self.do_exists(path.as_path()).ok().unwrap_or(false) {
             return Err(RemoteError::new(RemoteErrorType::NoSuchFileOrDirectory));
         }
         self.session.as_mut().unwrap().set_blocking(true);
@@ -639,6 +735,144 @@ impl RemoteFs for ScpFs {
     }
 }
 
+impl RemoteFs for ScpFs {
+    fn connect(&mut self) -> RemoteResult<Welcome> {
+        let connection = match self.opts.as_ref() {
+            Some(opts) => {
+                debug!("Initializing SFTP connection...");
+                commons::establish(opts)?
+            }
+            None => return commons::resume_session(self.session.as_ref()),
+        };
+        let mut session = connection.session;
+        // Get banner
+        let banner: Option<String> = session.banner().map(String::from);
+        debug!(
+            "Connection established: {}",
+            banner.as_deref().unwrap_or("")
+        );
+        // Get working directory
+        debug!("Getting working directory...");
+        self.wrkdir = commons::perform_shell_cmd(&mut session, "pwd")
+            .map(|x| PathBuf::from(x.as_str().trim()))?;
+        // Set session
+        self.session = Some(session);
+        self.keepalive = connection.keepalive;
+        self.info = Some(connection.info);
+        info!(
+            "Connection established; working directory: {}",
+            self.wrkdir.display()
+        );
+        Ok(Welcome::default().banner(banner))
+    }
+
+    fn disconnect(&mut self) -> RemoteResult<()> {
+        debug!("Disconnecting from remote...");
+        if self.opts.is_none() && self.session.is_some() {
+            // the session is not owned by the client; just release it
+            self.session = None;
+            self.info = None;
+            return Ok(());
+        }
+        if let Some(session) = self.session.as_ref() {
+            // Disconnect (greet server with 'Mandi' as they do in Friuli)
+            match session.disconnect(None, "Mandi!", None) {
+                Ok(_) => {
+                    // Set session and sftp to none
+                    self.session = None;
+                    self.keepalive = None;
+                    self.info = None;
+                    Ok(())
+                }
+                Err(err) => Err(RemoteError::new_ex(RemoteErrorType::ConnectionError, err)),
+            }
+        } else {
+            Err(RemoteError::new(RemoteErrorType::NotConnected))
+        }
+    }
+
+    fn is_connected(&mut self) -> bool {
+        self.session
+            .as_ref()
+            .map(|x| x.authenticated())
+            .unwrap_or(false)
+            && self
+                .keepalive
+                .as_ref()
+                .map(Keepalive::is_alive)
+                .unwrap_or(true)
+    }
+
+    fn pwd(&mut self) -> RemoteResult<PathBuf> {
+        self.check_connection()?;
+        Ok(self.wrkdir.clone())
+    }
+
+    fn change_dir(&mut self, dir: &Path) -> RemoteResult<PathBuf> {
+        self.with_retry(|fs| fs.do_change_dir(dir))
+    }
+
+    fn list_dir(&mut self, path: &Path) -> RemoteResult<Vec<File>> {
+        self.with_retry(|fs| fs.do_list_dir(path))
+    }
+
+    fn stat(&mut self, path: &Path) -> RemoteResult<File> {
+        self.with_retry(|fs| fs.do_stat(path))
+    }
+
+    fn exists(&mut self, path: &Path) -> RemoteResult<bool> {
+        self.with_retry(|fs| fs.do_exists(path))
+    }
+
+    fn setstat(&mut self, path: &Path, metadata: Metadata) -> RemoteResult<()> {
+        self.with_retry(|fs| fs.do_setstat(path, metadata.clone()))
+    }
+
+    fn remove_file(&mut self, path: &Path) -> RemoteResult<()> {
+        self.with_reconnect(|fs| fs.do_remove_file(path))
+    }
+
+    fn remove_dir(&mut self, path: &Path) -> RemoteResult<()> {
+        self.with_reconnect(|fs| fs.do_remove_dir(path))
+    }
+
+    fn remove_dir_all(&mut self, path: &Path) -> RemoteResult<()> {
+        self.with_reconnect(|fs| fs.do_remove_dir_all(path))
+    }
+
+    fn create_dir(&mut self, path: &Path, mode: UnixPex) -> RemoteResult<()> {
+        self.with_reconnect(|fs| fs.do_create_dir(path, mode))
+    }
+
+    fn symlink(&mut self, path: &Path, target: &Path) -> RemoteResult<()> {
+        self.with_reconnect(|fs| fs.do_symlink(path, target))
+    }
+
+    fn copy(&mut self, src: &Path, dest: &Path) -> RemoteResult<()> {
+        self.with_reconnect(|fs| fs.do_copy(src, dest))
+    }
+
+    fn mov(&mut self, src: &Path, dest: &Path) -> RemoteResult<()> {
+        self.with_reconnect(|fs| fs.do_mov(src, dest))
+    }
+
+    fn exec(&mut self, cmd: &str) -> RemoteResult<(u32, String)> {
+        self.with_reconnect(|fs| fs.do_exec(cmd))
+    }
+
+    fn append(&mut self, _path: &Path, _metadata: &Metadata) -> RemoteResult<WriteStream> {
+        Err(RemoteError::new(RemoteErrorType::UnsupportedFeature))
+    }
+
+    fn create(&mut self, path: &Path, metadata: &Metadata) -> RemoteResult<WriteStream> {
+        self.with_reconnect(|fs| fs.do_create(path, metadata))
+    }
+
+    fn open(&mut self, path: &Path) -> RemoteResult<ReadStream> {
+        self.with_retry(|fs| fs.do_open(path))
+    }
+}
+
 #[cfg(test)]
 mod test {
 
@@ -662,6 +896,34 @@ mod test {
         assert_eq!(client.is_connected(), false);
     }
 
+    #[test]
+    fn should_not_init_scp_fs_from_unauthenticated_session() {
+        let session = SshSession::new().unwrap();
+        assert_eq!(
+            ScpFs::from_session(session).err().unwrap().kind,
+            RemoteErrorType::AuthenticationFailed
+        );
+    }
+
+    #[test]
+    #[cfg(feature = "with-containers")]
+    #[serial]
+    fn should_init_scp_fs_from_session() {
+        crate::mock::logger();
+        let config_file = ssh_mock::create_ssh_config();
+        let opts = SshOpts::new("scp")
+            .key_storage(Box::new(ssh_mock::MockSshKeyStorage::default()))
+            .config_file(config_file.path(), ParseRule::ALLOW_UNKNOWN_FIELDS);
+        let session = commons::connect(&opts).unwrap();
+        let mut client = ScpFs::from_session(session).unwrap();
+        assert!(client.is_connected());
+        assert!(client.connect().is_ok());
+        assert_eq!(client.pwd().unwrap(), PathBuf::from("/config"));
+        assert!(client.disconnect().is_ok());
+        // can't connect again
+        assert!(client.connect().is_err());
+    }
+
     #[test]
     fn should_fail_connection_to_bad_server() {
         let mut client = ScpFs::new(SshOpts::new("mybad.verybad.server"));
@@ -737,8 +999,7 @@ mod test {
         let p = Path::new("a.txt");
         let file_data = "test data\n";
         let reader = Cursor::new(file_data.as_bytes());
-        let mut metadata = Metadata::default();
-        metadata.size = file_data.len() as u64;
+        let metadata = Metadata::default().size(file_data.len() as u64);
         assert!(client.create_file(p, &metadata, Box::new(reader)).is_ok());
         assert!(client.copy(p, Path::new("b.txt")).is_ok());
         assert!(client.stat(p).is_ok());
@@ -756,8 +1017,7 @@ mod test {
         let p = Path::new("a.txt");
         let file_data = "test data\n";
         let reader = Cursor::new(file_data.as_bytes());
-        let mut metadata = Metadata::default();
-        metadata.size = file_data.len() as u64;
+        let metadata = Metadata::default().size(file_data.len() as u64);
         assert!(client.create_file(p, &metadata, Box::new(reader)).is_ok());
         assert!(client.copy(p, Path::new("aaa/bbbb/ccc/b.txt")).is_err());
         finalize_client(client);
@@ -825,8 +1085,7 @@ mod test {
         let p = Path::new("a.txt");
         let file_data = "test data\n";
         let reader = Cursor::new(file_data.as_bytes());
-        let mut metadata = Metadata::default();
-        metadata.size = file_data.len() as u64;
+        let metadata = Metadata::default().size(file_data.len() as u64);
         assert_eq!(
             client
                 .create_file(p, &metadata, Box::new(reader))
@@ -849,8 +1108,7 @@ mod test {
         let p = Path::new("/tmp/ahsufhauiefhuiashf/hfhfhfhf");
         let file_data = "test data\n";
         let reader = Cursor::new(file_data.as_bytes());
-        let mut metadata = Metadata::default();
-        metadata.size = file_data.len() as u64;
+        let metadata = Metadata::default().size(file_data.len() as u64);
         assert!(client.create_file(p, &metadata, Box::new(reader)).is_err());
         finalize_client(client);
     }
@@ -879,8 +1137,7 @@ mod test {
         let p = Path::new("a.txt");
         let file_data = "test data\n";
         let reader = Cursor::new(file_data.as_bytes());
-        let mut metadata = Metadata::default();
-        metadata.size = file_data.len() as u64;
+        let metadata = Metadata::default().size(file_data.len() as u64);
         assert!(client.create_file(p, &metadata, Box::new(reader)).is_ok());
         // Verify size
         assert_eq!(client.exists(p).ok().unwrap(), true);
@@ -904,15 +1161,14 @@ mod test {
         let p = Path::new("a.txt");
         let file_data = "test data\n";
         let reader = Cursor::new(file_data.as_bytes());
-        let mut metadata = Metadata::default();
-        metadata.size = file_data.len() as u64;
+        let metadata = Metadata::default().size(file_data.len() as u64);
         assert!(client.create_file(p, &metadata, Box::new(reader)).is_ok());
         // Verify size
         let file = client
             .list_dir(wrkdir.as_path())
             .ok()
             .unwrap()
-            .get(0)
+            .first()
             .unwrap()
             .clone();
         assert_eq!(file.name().as_str(), "a.txt");
@@ -946,8 +1202,7 @@ mod test {
         let p = Path::new("a.txt");
         let file_data = "test data\n";
         let reader = Cursor::new(file_data.as_bytes());
-        let mut metadata = Metadata::default();
-        metadata.size = file_data.len() as u64;
+        let metadata = Metadata::default().size(file_data.len() as u64);
         assert!(client.create_file(p, &metadata, Box::new(reader)).is_ok());
         // Verify size
         let dest = Path::new("b.txt");
@@ -967,8 +1222,7 @@ mod test {
         let p = Path::new("a.txt");
         let file_data = "test data\n";
         let reader = Cursor::new(file_data.as_bytes());
-        let mut metadata = Metadata::default();
-        metadata.size = file_data.len() as u64;
+        let metadata = Metadata::default().size(file_data.len() as u64);
         assert!(client.create_file(p, &metadata, Box::new(reader)).is_ok());
         // Verify size
         let dest = Path::new("/tmp/wuefhiwuerfh/whjhh/b.txt");
@@ -1038,8 +1292,7 @@ mod test {
         file_path.push(Path::new("a.txt"));
         let file_data = "test data\n";
         let reader = Cursor::new(file_data.as_bytes());
-        let mut metadata = Metadata::default();
-        metadata.size = file_data.len() as u64;
+        let metadata = Metadata::default().size(file_data.len() as u64);
         assert!(client
             .create_file(file_path.as_path(), &metadata, Box::new(reader))
             .is_ok());
@@ -1094,8 +1347,7 @@ mod test {
         file_path.push(Path::new("a.txt"));
         let file_data = "test data\n";
         let reader = Cursor::new(file_data.as_bytes());
-        let mut metadata = Metadata::default();
-        metadata.size = file_data.len() as u64;
+        let metadata = Metadata::default().size(file_data.len() as u64);
         assert!(client
             .create_file(file_path.as_path(), &metadata, Box::new(reader))
             .is_ok());
@@ -1114,8 +1366,7 @@ mod test {
         let p = Path::new("a.txt");
         let file_data = "test data\n";
         let reader = Cursor::new(file_data.as_bytes());
-        let mut metadata = Metadata::default();
-        metadata.size = file_data.len() as u64;
+        let metadata = Metadata::default().size(file_data.len() as u64);
         assert!(client.create_file(p, &metadata, Box::new(reader)).is_ok());
         assert!(client.remove_file(p).is_ok());
         finalize_client(client);
@@ -1131,8 +1382,7 @@ mod test {
         let p = Path::new("a.sh");
         let file_data = "echo 5\n";
         let reader = Cursor::new(file_data.as_bytes());
-        let mut metadata = Metadata::default();
-        metadata.size = file_data.len() as u64;
+        let metadata = Metadata::default().size(file_data.len() as u64);
         assert!(client.create_file(p, &metadata, Box::new(reader)).is_ok());
 
         assert!(client
@@ -1199,8 +1449,7 @@ mod test {
         let p = Path::new("a.sh");
         let file_data = "echo 5\n";
         let reader = Cursor::new(file_data.as_bytes());
-        let mut metadata = Metadata::default();
-        metadata.size = file_data.len() as u64;
+        let metadata = Metadata::default().size(file_data.len() as u64);
         assert_eq!(
             client
                 .create_file(p, &metadata, Box::new(reader))
@@ -1241,8 +1490,7 @@ mod test {
         let p = Path::new("a.sh");
         let file_data = "echo 5\n";
         let reader = Cursor::new(file_data.as_bytes());
-        let mut metadata = Metadata::default();
-        metadata.size = file_data.len() as u64;
+        let metadata = Metadata::default().size(file_data.len() as u64);
         assert!(client.create_file(p, &metadata, Box::new(reader)).is_ok());
         let symlink = Path::new("b.sh");
         assert!(client.symlink(symlink, p).is_ok());
@@ -1260,8 +1508,7 @@ mod test {
         let p = Path::new("a.sh");
         let file_data = "echo 5\n";
         let reader = Cursor::new(file_data.as_bytes());
-        let mut metadata = Metadata::default();
-        metadata.size = file_data.len() as u64;
+        let metadata = Metadata::default().size(file_data.len() as u64);
         assert!(client.create_file(p, &metadata, Box::new(reader)).is_ok());
         let symlink = Path::new("b.sh");
         let file_data = "echo 5\n";
@@ -0,0 +1,62 @@
+//! ## Secret
+//!
+//! credentials wiped from memory when dropped
+
+use std::fmt;
+
+use zeroize::Zeroizing;
+
+/// A credential (e.g. a password or a passphrase), whose memory is zeroed when dropped.
+///
+/// The secret is never printed: `Debug` shows `Secret(****)`
+#[derive(Clone, Default, PartialEq, Eq)]
+pub struct Secret(Zeroizing<String>);
+
+impl Secret {
+    /// Instantiates a new `Secret`
+    pub fn new<S: Into<String>>(secret: S) -> Self {
+        Self(Zeroizing::new(secret.into()))
+    }
+
+    /// Get the secret value
+    pub fn expose(&self) -> &str {
+        &self.0
+    }
+}
+
+impl From<String> for Secret {
+    fn from(secret: String) -> Self {
+        Self::new(secret)
+    }
+}
+
+impl From<&str> for Secret {
+    fn from(secret: &str) -> Self {
+        Self::new(secret)
+    }
+}
+
+impl fmt::Debug for Secret {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str("Secret(****)")
+    }
+}
+
+#[cfg(test)]
+mod test {
+
+    use pretty_assertions::assert_eq;
+
+    use super::*;
+
+    #[test]
+    fn should_not_print_secret() {
+        let secret = Secret::from("qwerty123");
+        assert_eq!(secret.expose(), "qwerty123");
+        assert_eq!(format!("{secret:?}").as_str(), "Secret(****)");
+        assert_eq!(
+            format!("{:?}", Some(Secret::new(String::from("qwerty123")))).as_str(),
+            "Some(Secret(****))"
+        );
+    }
+}
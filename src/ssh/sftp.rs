@@ -15,7 +15,10 @@ use ssh2::{FileStat, OpenFlags, OpenType, RenameFlags};
 // -- export
 pub use ssh2::{Session as SshSession, Sftp as SshSftp};
 
-use super::{commons, SftpReadStream, SftpWriteStream, SshOpts};
+use super::keepalive::Keepalive;
+use super::reconnect;
+use super::timeout::{self, TimeoutGuard};
+use super::{commons, ConnectionInfo, SftpReadStream, SftpWriteStream, SshOpts};
 use crate::utils::path as path_utils;
 
 /// Sftp "filesystem" client
@@ -23,7 +26,12 @@ pub struct SftpFs {
     session: Option<SshSession>,
     sftp: Option<SshSftp>,
     wrkdir: PathBuf,
-    opts: SshOpts,
+    /// Options to connect with; `None` if the client has been created from a session
+    opts: Option<SshOpts>,
+    /// Keepalive of the session, if keepalives are enabled
+    keepalive: Option<Keepalive>,
+    /// Information about the connection, while connected
+    info: Option<ConnectionInfo>,
 }
 
 impl SftpFs {
@@ -33,31 +41,211 @@ impl SftpFs {
             session: None,
             sftp: None,
             wrkdir: PathBuf::from("/"),
-            opts,
+            opts: Some(opts),
+            keepalive: None,
+            info: None,
         }
     }
 
+    /// Creates a new `SftpFs` from an authenticated session, which is then used in blocking mode.
+    ///
+    /// The session can be shared with other clients (e.g. a `ScpFs`) by providing a clone of it.
+    /// The SFTP subsystem is opened, and the working directory resolved, at the first operation.
+    ///
+    /// The client is already connected; `disconnect` releases the session without closing it,
+    /// and `connect` can't establish a new session once the session has been released
+    pub fn from_session(session: SshSession) -> RemoteResult<Self> {
+        if !session.authenticated() {
+            return Err(RemoteError::new_ex(
+                RemoteErrorType::AuthenticationFailed,
+                "session is not authenticated",
+            ));
+        }
+        session.set_blocking(true);
+        Ok(Self {
+            session: Some(session),
+            sftp: None,
+            wrkdir: PathBuf::from("/"),
+            opts: None,
+            keepalive: None,
+            info: None,
+        })
+    }
+
     /// Get a reference to current `session` value.
     pub fn session(&mut self) -> Option<&mut SshSession> {
         self.session.as_mut()
     }
 
     /// Get a reference to current `sftp` value.
+    ///
+    /// If the SFTP subsystem hasn't been opened yet, it is opened
     pub fn sftp(&mut self) -> Option<&mut SshSftp> {
+        if self.sftp.is_none() && self.is_connected() {
+            let _ = self.open_sftp();
+        }
         self.sftp.as_mut()
     }
 
+    /// Get the information about the connection (host key of the server, negotiated algorithms
+    /// and authentication methods), while connected.
+    ///
+    /// `None` if the client has been created from a session, unless created by a `SharedSession`
+    pub fn connection_info(&self) -> Option<&ConnectionInfo> {
+        self.info.as_ref()
+    }
+
+    /// Set the information about the connection of the session the client has been created from
+    pub(crate) fn with_connection_info(mut self, info: Option<ConnectionInfo>) -> Self {
+        self.info = info;
+        self
+    }
+
+    /// Check whether the server answers, waiting up to `timeout`.
+    ///
+    /// If the server doesn't answer, the session is released and the client is no longer connected
+    pub fn ping(&mut self, timeout: Duration) -> RemoteResult<()> {
+        let session = self
+            .session
+            .as_ref()
+            .ok_or_else(|| RemoteError::new(RemoteErrorType::NotConnected))?;
+        if let Err(err) = commons::ping(session, timeout) {
+            self.session = None;
+            self.sftp = None;
+            self.keepalive = None;
+            self.info = None;
+            return Err(err);
+        }
+        Ok(())
+    }
+
+    /// Run `op` on the client with the operation timeout set to `timeout` (zero disables it),
+    /// e.g. to allow a longer time for a large transfer; the previous timeout is restored afterwards.
+    ///
+    /// The timeout applies to the whole session, thus to other clients sharing it, while `op` runs
+    pub fn with_timeout<T, F>(&mut self, timeout: Duration, op: F) -> RemoteResult<T>
+    where
+        F: FnOnce(&mut Self) -> RemoteResult<T>,
+    {
+        let _guard = self
+            .session
+            .clone()
+            .map(|session| TimeoutGuard::new(session, timeout));
+        op(self)
+    }
+
     // -- private
 
-    /// Check connection status
+    /// Check connection status, opening the SFTP subsystem if it hasn't been opened yet
     fn check_connection(&mut self) -> RemoteResult<()> {
-        if self.is_connected() {
-            Ok(())
-        } else {
-            Err(RemoteError::new(RemoteErrorType::NotConnected))
+        if !self.is_connected() {
+            return Err(RemoteError::new(RemoteErrorType::NotConnected));
+        }
+        if self.sftp.is_none() {
+            self.open_sftp()?;
+        }
+        Ok(())
+    }
+
+    /// Run the idempotent operation `op`; if it fails because the connection was lost
+    /// and auto reconnect is enabled, reconnect and run it again.
+    /// A failure caused by the operation timeout is reported as a `TIMED_OUT` error
+    fn with_retry<T, F>(&mut self, mut op: F) -> RemoteResult<T>
+    where
+        F: FnMut(&mut Self) -> RemoteResult<T>,
+    {
+        let result = match op(self) {
+            Err(err) if self.connection_lost() => {
+                self.reconnect(&err)?;
+                op(self)
+            }
+            result => result,
+        };
+        result.map_err(|err| self.timed_out(err))
+    }
+
+    /// Run the operation `op`; if it fails because the connection was lost and auto reconnect is enabled,
+    /// reconnect and return a `NOT_RETRIED` error, since the operation may not be idempotent.
+    /// A failure caused by the operation timeout is reported as a `TIMED_OUT` error
+    fn with_reconnect<T, F>(&mut self, op: F) -> RemoteResult<T>
+    where
+        F: FnOnce(&mut Self) -> RemoteResult<T>,
+    {
+        match op(self) {
+            Err(err) if self.connection_lost() => {
+                self.reconnect(&err)?;
+                Err(reconnect::not_retried(err))
+            }
+            result => result.map_err(|err| self.timed_out(err)),
         }
     }
 
+    /// Report the failure `err` as a `TIMED_OUT` error if it was caused by the operation timeout.
+    ///
+    /// The session may then be out of sync with the server, thus it is released and the client is no longer connected
+    fn timed_out(&mut self, err: RemoteError) -> RemoteError {
+        let err = timeout::timed_out(self.session.as_ref(), err);
+        if timeout::is_timeout(&err) {
+            warn!("Releasing the session after the operation timed out");
+            self.session = None;
+            self.sftp = None;
+            self.keepalive = None;
+            self.info = None;
+        }
+        err
+    }
+
+    /// Returns whether auto reconnect is enabled and the connection has been lost
+    fn connection_lost(&self) -> bool {
+        self.opts
+            .as_ref()
+            .map(|x| x.auto_reconnect)
+            .unwrap_or(false)
+            && reconnect::connection_lost(self.session.as_ref(), self.keepalive.as_ref())
+    }
+
+    /// Connect again after the connection was lost with `err`, restoring the working directory
+    fn reconnect(&mut self, err: &RemoteError) -> RemoteResult<()> {
+        warn!("Connection lost ({err}); reconnecting...");
+        let wrkdir = self.wrkdir.clone();
+        self.session = None;
+        self.sftp = None;
+        self.keepalive = None;
+        self.info = None;
+        self.connect()?;
+        self.wrkdir = wrkdir;
+        info!(
+            "Reconnected; working directory restored to {}",
+            self.wrkdir.display()
+        );
+        Ok(())
+    }
+
+    /// Open the SFTP subsystem on the current session and get the working directory
+    fn open_sftp(&mut self) -> RemoteResult<()> {
+        let session = self
+            .session
+            .as_ref()
+            .ok_or_else(|| RemoteError::new(RemoteErrorType::NotConnected))?;
+        // Get Sftp client
+        debug!("Getting SFTP client...");
+        let sftp = match session.sftp() {
+            Ok(s) => s,
+            Err(err) => {
+                error!("Could not get sftp client: {}", err);
+                return Err(RemoteError::new_ex(RemoteErrorType::ProtocolError, err));
+            }
+        };
+        // Get working directory
+        debug!("Getting working directory...");
+        self.wrkdir = match sftp.realpath(Path::new(".")) {
+            Ok(p) => p,
+            Err(err) => return Err(RemoteError::new_ex(RemoteErrorType::ProtocolError, err)),
+        };
+        self.sftp = Some(sftp);
+        Ok(())
+    }
+
     /// Make fsentry from SFTP stat
     fn make_fsentry(&self, path: &Path, metadata: &FileStat) -> File {
         let name = match path.file_name() {
@@ -139,73 +327,14 @@ impl SftpFs {
     }
 }
 
-impl RemoteFs for SftpFs {
-    fn connect(&mut self) -> RemoteResult<Welcome> {
-        debug!("Initializing SFTP connection...");
-        let session = commons::connect(&self.opts)?;
-        // Set blocking to true
-        session.set_blocking(true);
-        // Get Sftp client
-        debug!("Getting SFTP client...");
-        let sftp = match session.sftp() {
-            Ok(s) => s,
-            Err(err) => {
-                error!("Could not get sftp client: {}", err);
-                return Err(RemoteError::new_ex(RemoteErrorType::ProtocolError, err));
-            }
-        };
-        // Get working directory
-        debug!("Getting working directory...");
-        self.wrkdir = match sftp.realpath(Path::new(".")) {
-            Ok(p) => p,
-            Err(err) => return Err(RemoteError::new_ex(RemoteErrorType::ProtocolError, err)),
-        };
-        self.session = Some(session);
-        self.sftp = Some(sftp);
-        let banner: Option<String> = self.session.as_ref().unwrap().banner().map(String::from);
-        debug!(
-            "Connection established: '{}'; working directory {}",
-            banner.as_deref().unwrap_or(""),
-            self.wrkdir.display()
-        );
-        Ok(Welcome::default().banner(banner))
-    }
-
-    fn disconnect(&mut self) -> RemoteResult<()> {
-        debug!("Disconnecting from remote...");
-        if let Some(session) = self.session.as_ref() {
-            // Disconnect (greet server with 'Mandi' as they do in Friuli)
-            match session.disconnect(None, "Mandi!", None) {
-                Ok(_) => {
-                    // Set session and sftp to none
-                    self.session = None;
-                    self.sftp = None;
-                    Ok(())
-                }
-                Err(err) => Err(RemoteError::new_ex(RemoteErrorType::ConnectionError, err)),
-            }
-        } else {
-            Err(RemoteError::new(RemoteErrorType::NotConnected))
-        }
-    }
-
-    fn is_connected(&mut self) -> bool {
-        self.session
-            .as_ref()
-            .map(|x| x.authenticated())
-            .unwrap_or(false)
-    }
-
-    fn pwd(&mut self) -> RemoteResult<PathBuf> {
-        self.check_connection()?;
-        Ok(self.wrkdir.clone())
-    }
+impl SftpFs {
+    // -- operations, run once by the `RemoteFs` methods with the reconnect policy
 
-    fn change_dir(&mut self, dir: &Path) -> RemoteResult<PathBuf> {
+    fn do_change_dir(&mut self, dir: &Path) -> RemoteResult<PathBuf> {
         self.check_connection()?;
         let dir = path_utils::absolutize(self.wrkdir.as_path(), dir);
         // Stat path to check if it exists. If it is a file, return error
-        match self.stat(dir.as_path()) {
+        match self.do_stat(dir.as_path()) {
             Err(err) => Err(err),
             Ok(file) if file.is_dir() => {
                 self.wrkdir = dir;
@@ -219,54 +348,48 @@ impl RemoteFs for SftpFs {
         }
     }
 
-    fn list_dir(&mut self, path: &Path) -> RemoteResult<Vec<File>> {
-        if let Some(sftp) = self.sftp.as_ref() {
-            let path = path_utils::absolutize(self.wrkdir.as_path(), path);
-            debug!("Reading directory content of {}", path.display());
-            match sftp.readdir(path.as_path()) {
-                Err(err) => Err(RemoteError::new_ex(RemoteErrorType::StatFailed, err)),
-                Ok(files) => Ok(files
-                    .iter()
-                    .map(|(path, metadata)| self.make_fsentry(path.as_path(), metadata))
-                    .collect()),
-            }
-        } else {
-            Err(RemoteError::new(RemoteErrorType::NotConnected))
+    fn do_list_dir(&mut self, path: &Path) -> RemoteResult<Vec<File>> {
+        self.check_connection()?;
+        let sftp = self.sftp.as_ref().unwrap();
+        let path = path_utils::absolutize(self.wrkdir.as_path(), path);
+        debug!("Reading directory content of {}", path.display());
+        match sftp.readdir(path.as_path()) {
+            Err(err) => Err(RemoteError::new_ex(RemoteErrorType::StatFailed, err)),
+            Ok(files) => Ok(files
+                .iter()
+                .map(|(path, metadata)| self.make_fsentry(path.as_path(), metadata))
+                .collect()),
         }
     }
 
-    fn stat(&mut self, path: &Path) -> RemoteResult<File> {
-        if let Some(sftp) = self.sftp.as_ref() {
-            let path = path_utils::absolutize(self.wrkdir.as_path(), path);
-            debug!("Collecting metadata for {}", path.display());
-            sftp.stat(path.as_path())
-                .map(|x| self.make_fsentry(path.as_path(), &x))
-                .map_err(|e| {
-                    error!("Stat failed: {}", e);
-                    RemoteError::new_ex(RemoteErrorType::NoSuchFileOrDirectory, e)
-                })
-        } else {
-            Err(RemoteError::new(RemoteErrorType::NotConnected))
-        }
+    fn do_stat(&mut self, path: &Path) -> RemoteResult<File> {
+        self.check_connection()?;
+        let sftp = self.sftp.as_ref().unwrap();
+        let path = path_utils::absolutize(self.wrkdir.as_path(), path);
+        debug!("Collecting metadata for {}", path.display());
+        sftp.stat(path.as_path())
+            .map(|x| self.make_fsentry(path.as_path(), &x))
+            .map_err(|e| {
+                error!("Stat failed: {}", e);
+                RemoteError::new_ex(RemoteErrorType::NoSuchFileOrDirectory, e)
+            })
     }
 
-    fn setstat(&mut self, path: &Path, metadata: Metadata) -> RemoteResult<()> {
-        if let Some(sftp) = self.sftp.as_ref() {
-            let path = path_utils::absolutize(self.wrkdir.as_path(), path);
-            debug!("Setting metadata for {}", path.display());
-            sftp.setstat(path.as_path(), Self::metadata_to_filestat(metadata))
-                .map(|_| ())
-                .map_err(|e| {
-                    error!("Setstat failed: {}", e);
-                    RemoteError::new_ex(RemoteErrorType::StatFailed, e)
-                })
-        } else {
-            Err(RemoteError::new(RemoteErrorType::NotConnected))
-        }
+    fn do_setstat(&mut self, path: &Path, metadata: Metadata) -> RemoteResult<()> {
+        self.check_connection()?;
+        let sftp = self.sftp.as_ref().unwrap();
+        let path = path_utils::absolutize(self.wrkdir.as_path(), path);
+        debug!("Setting metadata for {}", path.display());
+        sftp.setstat(path.as_path(), Self::metadata_to_filestat(metadata))
+            .map(|_| ())
+            .map_err(|e| {
+                error!("Setstat failed: {}", e);
+                RemoteError::new_ex(RemoteErrorType::StatFailed, e)
+            })
     }
 
-    fn exists(&mut self, path: &Path) -> RemoteResult<bool> {
-        match self.stat(path) {
+    fn do_exists(&mut self, path: &Path) -> RemoteResult<bool> {
+        match self.do_stat(path) {
             Ok(_) => Ok(true),
             Err(RemoteError {
                 kind: RemoteErrorType::NoSuchFileOrDirectory,
@@ -276,33 +399,29 @@ impl RemoteFs for SftpFs {
         }
     }
 
-    fn remove_file(&mut self, path: &Path) -> RemoteResult<()> {
-        if let Some(sftp) = self.sftp.as_ref() {
-            let path = path_utils::absolutize(self.wrkdir.as_path(), path);
-            debug!("Remove file {}", path.display());
-            sftp.unlink(path.as_path()).map_err(|e| {
-                error!("Remove failed: {}", e);
-                RemoteError::new_ex(RemoteErrorType::CouldNotRemoveFile, e)
-            })
-        } else {
-            Err(RemoteError::new(RemoteErrorType::NotConnected))
-        }
+    fn do_remove_file(&mut self, path: &Path) -> RemoteResult<()> {
+        self.check_connection()?;
+        let sftp = self.sftp.as_ref().unwrap();
+        let path = path_utils::absolutize(self.wrkdir.as_path(), path);
+        debug!("Remove file {}", path.display());
+        sftp.unlink(path.as_path()).map_err(|e| {
+            error!("Remove failed: {}", e);
+            RemoteError::new_ex(RemoteErrorType::CouldNotRemoveFile, e)
+        })
     }
 
-    fn remove_dir(&mut self, path: &Path) -> RemoteResult<()> {
-        if let Some(sftp) = self.sftp.as_ref() {
-            let path = path_utils::absolutize(self.wrkdir.as_path(), path);
-            debug!("Remove dir {}", path.display());
-            sftp.rmdir(path.as_path()).map_err(|e| {
-                error!("Remove failed: {}", e);
-                RemoteError::new_ex(RemoteErrorType::CouldNotRemoveFile, e)
-            })
-        } else {
-            Err(RemoteError::new(RemoteErrorType::NotConnected))
-        }
+    fn do_remove_dir(&mut self, path: &Path) -> RemoteResult<()> {
+        self.check_connection()?;
+        let sftp = self.sftp.as_ref().unwrap();
+        let path = path_utils::absolutize(self.wrkdir.as_path(), path);
+        debug!("Remove dir {}", path.display());
+        sftp.rmdir(path.as_path()).map_err(|e| {
+            error!("Remove failed: {}", e);
+            RemoteError::new_ex(RemoteErrorType::CouldNotRemoveFile, e)
+        })
     }
 
-    fn create_dir(&mut self, path: &Path, mode: UnixPex) -> RemoteResult<()> {
+    fn do_create_dir(&mut self, path: &Path, mode: UnixPex) -> RemoteResult<()> {
         self.check_connection()?;
         let path = path_utils::absolutize(self.wrkdir.as_path(), path);
         // Check if already exists
@@ -311,7 +430,7 @@ impl RemoteFs for SftpFs {
             path.display(),
             u32::from(mode)
         );
-        if self.exists(path.as_path())? {
+        if self.do_exists(path.as_path())? {
             error!("directory {} already exists", path.display());
             return Err(RemoteError::new(RemoteErrorType::DirectoryAlreadyExists));
         }
@@ -325,7 +444,7 @@ impl RemoteFs for SftpFs {
             })
     }
 
-    fn symlink(&mut self, path: &Path, target: &Path) -> RemoteResult<()> {
+    fn do_symlink(&mut self, path: &Path, target: &Path) -> RemoteResult<()> {
         self.check_connection()?;
         let path = path_utils::absolutize(self.wrkdir.as_path(), path);
         // Check if already exists
@@ -334,7 +453,7 @@ impl RemoteFs for SftpFs {
             path.display(),
             target.display()
         );
-        if !self.exists(target)? {
+        if !self.do_exists(target)? {
             error!("target {} doesn't exist", target.display());
             return Err(RemoteError::new(RemoteErrorType::NoSuchFileOrDirectory));
         }
@@ -348,11 +467,11 @@ impl RemoteFs for SftpFs {
             })
     }
 
-    fn copy(&mut self, src: &Path, dest: &Path) -> RemoteResult<()> {
+    fn do_copy(&mut self, src: &Path, dest: &Path) -> RemoteResult<()> {
         self.check_connection()?;
         let src = path_utils::absolutize(self.wrkdir.as_path(), src);
         // check if file exists
-        if !self.exists(src.as_path()).ok().unwrap_or(false) {
+        if !self.do_exists(src.as_path()).ok().unwrap_or(false) {
             return Err(RemoteError::new(RemoteErrorType::NoSuchFileOrDirectory));
         }
         let dest = path_utils::absolutize(self.wrkdir.as_path(), dest);
@@ -372,11 +491,11 @@ impl RemoteFs for SftpFs {
         }
     }
 
-    fn mov(&mut self, src: &Path, dest: &Path) -> RemoteResult<()> {
+    fn do_mov(&mut self, src: &Path, dest: &Path) -> RemoteResult<()> {
         self.check_connection()?;
         let src = path_utils::absolutize(self.wrkdir.as_path(), src);
         // check if file exists
-        if !self.exists(src.as_path()).ok().unwrap_or(false) {
+        if !self.do_exists(src.as_path()).ok().unwrap_or(false) {
             return Err(RemoteError::new(RemoteErrorType::NoSuchFileOrDirectory));
         }
         let dest = path_utils::absolutize(self.wrkdir.as_path(), dest);
@@ -391,7 +510,7 @@ impl RemoteFs for SftpFs {
             })
     }
 
-    fn exec(&mut self, cmd: &str) -> RemoteResult<(u32, String)> {
+    fn do_exec(&mut self, cmd: &str) -> RemoteResult<(u32, String)> {
         self.check_connection()?;
         debug!(r#"Executing command "{}""#, cmd);
         commons::perform_shell_cmd_at_with_rc(
@@ -401,55 +520,51 @@ impl RemoteFs for SftpFs {
         )
     }
 
-    fn append(&mut self, path: &Path, metadata: &Metadata) -> RemoteResult<WriteStream> {
-        if let Some(sftp) = self.sftp.as_ref() {
-            let path = path_utils::absolutize(self.wrkdir.as_path(), path);
-            debug!("Opening file at {} for appending", path.display());
-            let mode = metadata.mode.map(|x| u32::from(x) as i32).unwrap_or(0o644);
-            sftp.open_mode(
-                path.as_path(),
-                OpenFlags::CREATE | OpenFlags::APPEND | OpenFlags::WRITE,
-                mode,
-                OpenType::File,
-            )
-            .map(SftpWriteStream::from)
-            .map(WriteStream::from)
-            .map_err(|e| {
-                error!("Append failed: {}", e);
-                RemoteError::new_ex(RemoteErrorType::CouldNotOpenFile, e)
-            })
-        } else {
-            Err(RemoteError::new(RemoteErrorType::NotConnected))
-        }
+    fn do_append(&mut self, path: &Path, metadata: &Metadata) -> RemoteResult<WriteStream> {
+        self.check_connection()?;
+        let sftp = self.sftp.as_ref().unwrap();
+        let path = path_utils::absolutize(self.wrkdir.as_path(), path);
+        debug!("Opening file at {} for appending", path.display());
+        let mode = metadata.mode.map(|x| u32::from(x) as i32).unwrap_or(0o644);
+        sftp.open_mode(
+            path.as_path(),
+            OpenFlags::CREATE | OpenFlags::APPEND | OpenFlags::WRITE,
+            mode,
+            OpenType::File,
+        )
+        .map(SftpWriteStream::from)
+        .map(WriteStream::from)
+        .map_err(|e| {
+            error!("Append failed: {}", e);
+            RemoteError::new_ex(RemoteErrorType::CouldNotOpenFile, e)
+        })
     }
 
-    fn create(&mut self, path: &Path, metadata: &Metadata) -> RemoteResult<WriteStream> {
-        if let Some(sftp) = self.sftp.as_ref() {
-            let path = path_utils::absolutize(self.wrkdir.as_path(), path);
-            debug!("Creating file at {}", path.display());
-            let mode = metadata.mode.map(|x| u32::from(x) as i32).unwrap_or(0o644);
-            sftp.open_mode(
-                path.as_path(),
-                OpenFlags::CREATE | OpenFlags::WRITE | OpenFlags::TRUNCATE,
-                mode,
-                OpenType::File,
-            )
-            .map(SftpWriteStream::from)
-            .map(WriteStream::from)
-            .map_err(|e| {
-                error!("Create failed: {}", e);
-                RemoteError::new_ex(RemoteErrorType::FileCreateDenied, e)
-            })
-        } else {
-            Err(RemoteError::new(RemoteErrorType::NotConnected))
-        }
+    fn do_create(&mut self, path: &Path, metadata: &Metadata) -> RemoteResult<WriteStream> {
+        self.check_connection()?;
+        let sftp = self.sftp.as_ref().unwrap();
+        let path = path_utils::absolutize(self.wrkdir.as_path(), path);
+        debug!("Creating file at {}", path.display());
+        let mode = metadata.mode.map(|x| u32::from(x) as i32).unwrap_or(0o644);
+        sftp.open_mode(
+            path.as_path(),
+            OpenFlags::CREATE | OpenFlags::WRITE | OpenFlags::TRUNCATE,
+            mode,
+            OpenType::File,
+        )
+        .map(SftpWriteStream::from)
+        .map(WriteStream::from)
+        .map_err(|e| {
+            error!("Create failed: {}", e);
+            RemoteError::new_ex(RemoteErrorType::FileCreateDenied, e)
+        })
     }
 
-    fn open(&mut self, path: &Path) -> RemoteResult<ReadStream> {
+    fn do_open(&mut self, path: &Path) -> RemoteResult<ReadStream> {
         self.check_connection()?;
         let path = path_utils::absolutize(self.wrkdir.as_path(), path);
         // check if file exists
-        if !self.exists(path.as_path()).ok().unwrap_or(false) {
+        if !self.do_exists(path.as_path()).ok().unwrap_or(false) {
             return Err(RemoteError::new(RemoteErrorType::NoSuchFileOrDirectory));
         }
         debug!("Opening file at {}", path.display());
@@ -465,16 +580,14 @@ impl RemoteFs for SftpFs {
             })
     }
 
-    // -- override (std::io::copy is VERY slow on SFTP <https://github.com/remotefs-rs/remotefs-rs/issues/6>)
-
-    fn append_file(
+    fn do_append_file(
         &mut self,
         path: &Path,
         metadata: &Metadata,
         mut reader: Box<dyn Read + Send>,
     ) -> RemoteResult<u64> {
         if self.is_connected() {
-            let mut stream = self.append(path, metadata)?;
+            let mut stream = self.do_append(path, metadata)?;
             trace!("Opened remote file");
             let mut bytes: usize = 0;
             let transfer_size = metadata.size as usize;
@@ -501,14 +614,14 @@ impl RemoteFs for SftpFs {
         }
     }
 
-    fn create_file(
+    fn do_create_file(
         &mut self,
         path: &Path,
         metadata: &Metadata,
         mut reader: Box<dyn std::io::Read + Send>,
     ) -> RemoteResult<u64> {
         if self.is_connected() {
-            let mut stream = self.create(path, metadata)?;
+            let mut stream = self.do_create(path, metadata)?;
             trace!("Opened remote file");
             let mut bytes: usize = 0;
             let transfer_size = metadata.size as usize;
@@ -535,10 +648,10 @@ impl RemoteFs for SftpFs {
         }
     }
 
-    fn open_file(&mut self, src: &Path, mut dest: Box<dyn Write + Send>) -> RemoteResult<u64> {
+    fn do_open_file(&mut self, src: &Path, mut dest: Box<dyn Write + Send>) -> RemoteResult<u64> {
         if self.is_connected() {
-            let transfer_size = self.stat(src)?.metadata().size as usize;
-            let mut stream = self.open(src)?;
+            let transfer_size = self.do_stat(src)?.metadata().size as usize;
+            let mut stream = self.do_open(src)?;
             trace!("File opened");
             let mut bytes: usize = 0;
             while bytes < transfer_size {
@@ -565,6 +678,163 @@ impl RemoteFs for SftpFs {
     }
 }
 
+impl RemoteFs for SftpFs {
+    fn connect(&mut self) -> RemoteResult<Welcome> {
+        let connection = match self.opts.as_ref() {
+            Some(opts) => {
+                debug!("Initializing SFTP connection...");
+                commons::establish(opts)?
+            }
+            None => return commons::resume_session(self.session.as_ref()),
+        };
+        // Set blocking to true
+        connection.session.set_blocking(true);
+        self.session = Some(connection.session);
+        self.sftp = None;
+        if let Err(err) = self.open_sftp() {
+            self.session = None;
+            return Err(err);
+        }
+        self.keepalive = connection.keepalive;
+        self.info = Some(connection.info);
+        let banner: Option<String> = self.session.as_ref().unwrap().banner().map(String::from);
+        debug!(
+            "Connection established: '{}'; working directory {}",
+            banner.as_deref().unwrap_or(""),
+            self.wrkdir.display()
+        );
+        Ok(Welcome::default().banner(banner))
+    }
+
+    fn disconnect(&mut self) -> RemoteResult<()> {
+        debug!("Disconnecting from remote...");
+        if self.opts.is_none() && self.session.is_some() {
+            // the session is not owned by the client; just release it
+            self.session = None;
+            self.info = None;
+            self.sftp = None;
+            return Ok(());
+        }
+        if let Some(session) = self.session.as_ref() {
+            // Disconnect (greet server with 'Mandi' as they do in Friuli)
+            match session.disconnect(None, "Mandi!", None) {
+                Ok(_) => {
+                    // Set session and sftp to none
+                    self.session = None;
+                    self.sftp = None;
+                    self.keepalive = None;
+                    self.info = None;
+                    Ok(())
+                }
+                Err(err) => Err(RemoteError::new_ex(RemoteErrorType::ConnectionError, err)),
+            }
+        } else {
+            Err(RemoteError::new(RemoteErrorType::NotConnected))
+        }
+    }
+
+    fn is_connected(&mut self) -> bool {
+        self.session
+            .as_ref()
+            .map(|x| x.authenticated())
+            .unwrap_or(false)
+            && self
+                .keepalive
+                .as_ref()
+                .map(Keepalive::is_alive)
+                .unwrap_or(true)
+    }
+
+    fn pwd(&mut self) -> RemoteResult<PathBuf> {
+        self.check_connection()?;
+        Ok(self.wrkdir.clone())
+    }
+
+    fn change_dir(&mut self, dir: &Path) -> RemoteResult<PathBuf> {
+        self.with_retry(|fs| fs.do_change_dir(dir))
+    }
+
+    fn list_dir(&mut self, path: &Path) -> RemoteResult<Vec<File>> {
+        self.with_retry(|fs| fs.do_list_dir(path))
+    }
+
+    fn stat(&mut self, path: &Path) -> RemoteResult<File> {
+        self.with_retry(|fs| fs.do_stat(path))
+    }
+
+    fn setstat(&mut self, path: &Path, metadata: Metadata) -> RemoteResult<()> {
+        self.with_retry(|fs| fs.do_setstat(path, metadata.clone()))
+    }
+
+    fn exists(&mut self, path: &Path) -> RemoteResult<bool> {
+        self.with_retry(|fs| fs.do_exists(path))
+    }
+
+    fn remove_file(&mut self, path: &Path) -> RemoteResult<()> {
+        self.with_reconnect(|fs| fs.do_remove_file(path))
+    }
+
+    fn remove_dir(&mut self, path: &Path) -> RemoteResult<()> {
+        self.with_reconnect(|fs| fs.do_remove_dir(path))
+    }
+
+    fn create_dir(&mut self, path: &Path, mode: UnixPex) -> RemoteResult<()> {
+        self.with_reconnect(|fs| fs.do_create_dir(path, mode))
+    }
+
+    fn symlink(&mut self, path: &Path, target: &Path) -> RemoteResult<()> {
+        self.with_reconnect(|fs| fs.do_symlink(path, target))
+    }
+
+    fn copy(&mut self, src: &Path, dest: &Path) -> RemoteResult<()> {
+        self.with_reconnect(|fs| fs.do_copy(src, dest))
+    }
+
+    fn mov(&mut self, src: &Path, dest: &Path) -> RemoteResult<()> {
+        self.with_reconnect(|fs| fs.do_mov(src, dest))
+    }
+
+    fn exec(&mut self, cmd: &str) -> RemoteResult<(u32, String)> {
+        self.with_reconnect(|fs| fs.do_exec(cmd))
+    }
+
+    fn append(&mut self, path: &Path, metadata: &Metadata) -> RemoteResult<WriteStream> {
+        self.with_reconnect(|fs| fs.do_append(path, metadata))
+    }
+
+    fn create(&mut self, path: &Path, metadata: &Metadata) -> RemoteResult<WriteStream> {
+        self.with_reconnect(|fs| fs.do_create(path, metadata))
+    }
+
+    fn open(&mut self, path: &Path) -> RemoteResult<ReadStream> {
+        self.with_retry(|fs| fs.do_open(path))
+    }
+
+    // -- override (std::io::copy is VERY slow on SFTP <https://github.com/remotefs-rs/remotefs-rs/issues/6>)
+
+    fn append_file(
+        &mut self,
+        path: &Path,
+        metadata: &Metadata,
+        reader: Box<dyn Read + Send>,
+    ) -> RemoteResult<u64> {
+        self.with_reconnect(|fs| fs.do_append_file(path, metadata, reader))
+    }
+
+    fn create_file(
+        &mut self,
+        path: &Path,
+        metadata: &Metadata,
+        reader: Box<dyn std::io::Read + Send>,
+    ) -> RemoteResult<u64> {
+        self.with_reconnect(|fs| fs.do_create_file(path, metadata, reader))
+    }
+
+    fn open_file(&mut self, src: &Path, dest: Box<dyn Write + Send>) -> RemoteResult<u64> {
+        self.with_reconnect(|fs| fs.do_open_file(src, dest))
+    }
+}
+
 #[cfg(test)]
 mod test {
 
@@ -590,6 +860,221 @@ mod test {
         assert_eq!(client.is_connected(), false);
     }
 
+    #[test]
+    #[cfg(feature = "with-containers")]
+    #[serial]
+    fn should_reconnect_when_connection_is_lost() {
+        use std::net::{Shutdown, TcpStream};
+        use std::sync::{Arc, Mutex};
+
+        use crate::{is_not_retried, SessionStream, SshConnector};
+
+        /// Connector keeping the streams, to break them
+        struct BreakableConnector(Arc<Mutex<Vec<TcpStream>>>);
+
+        impl SshConnector for BreakableConnector {
+            fn connect(&self, host: &str, port: u16) -> std::io::Result<Box<dyn SessionStream>> {
+                let stream = TcpStream::connect((host, port))?;
+                self.0.lock().unwrap().push(stream.try_clone()?);
+                Ok(Box::new(stream))
+            }
+        }
+
+        crate::mock::logger();
+        let streams = Arc::new(Mutex::new(Vec::<TcpStream>::new()));
+        let break_connection = || {
+            for stream in streams.lock().unwrap().drain(..) {
+                let _ = stream.shutdown(Shutdown::Both);
+            }
+        };
+        let config_file = ssh_mock::create_ssh_config();
+        let mut client = SftpFs::new(
+            SshOpts::new("sftp")
+                .key_storage(Box::new(ssh_mock::MockSshKeyStorage::default()))
+                .config_file(config_file.path(), ParseRule::ALLOW_UNKNOWN_FIELDS)
+                .connector(Box::new(BreakableConnector(streams.clone())))
+                .auto_reconnect(true),
+        );
+        assert!(client.connect().is_ok());
+        assert!(client.change_dir(Path::new("/tmp")).is_ok());
+        // idempotent operations are retried
+        break_connection();
+        assert!(client.list_dir(Path::new(".")).is_ok());
+        assert_eq!(client.pwd().unwrap(), PathBuf::from("/tmp"));
+        // other operations are not
+        break_connection();
+        let err = client
+            .remove_file(Path::new("/tmp/not_retried.txt"))
+            .err()
+            .unwrap();
+        assert!(is_not_retried(&err));
+        assert!(client.is_connected());
+        assert!(client.disconnect().is_ok());
+    }
+
+    #[test]
+    #[cfg(feature = "with-containers")]
+    #[serial]
+    fn should_time_out_when_server_stalls() {
+        use std::net::TcpStream;
+        use std::sync::atomic::{AtomicBool, Ordering};
+        use std::sync::Arc;
+
+        use crate::ssh::tunnel::socket_pair;
+        use crate::{is_timeout, SessionStream, SshConnector};
+
+        /// Connector relaying the session, which stops forwarding data while stalled
+        struct StallingConnector(Arc<AtomicBool>);
+
+        impl SshConnector for StallingConnector {
+            fn connect(&self, host: &str, port: u16) -> std::io::Result<Box<dyn SessionStream>> {
+                let server = TcpStream::connect((host, port))?;
+                let (client, relay) = socket_pair()?;
+                relay_data(relay.try_clone()?, server.try_clone()?, self.0.clone());
+                relay_data(server, relay, self.0.clone());
+                Ok(Box::new(client))
+            }
+        }
+
+        fn relay_data(
+            mut from: impl Read + Send + 'static,
+            mut to: impl Write + Send + 'static,
+            stalled: Arc<AtomicBool>,
+        ) {
+            std::thread::spawn(move || {
+                let mut buf = [0; 8192];
+                while let Ok(n) = from.read(&mut buf) {
+                    while stalled.load(Ordering::Relaxed) {
+                        std::thread::sleep(Duration::from_millis(10));
+                    }
+                    if n == 0 || to.write_all(&buf[..n]).is_err() {
+                        break;
+                    }
+                }
+            });
+        }
+
+        crate::mock::logger();
+        let stalled = Arc::new(AtomicBool::new(false));
+        let config_file = ssh_mock::create_ssh_config();
+        let mut client = SftpFs::new(
+            SshOpts::new("sftp")
+                .key_storage(Box::new(ssh_mock::MockSshKeyStorage::default()))
+                .config_file(config_file.path(), ParseRule::ALLOW_UNKNOWN_FIELDS)
+                .connector(Box::new(StallingConnector(stalled.clone())))
+                .operation_timeout(Duration::from_secs(5)),
+        );
+        assert!(client.connect().is_ok());
+        // the timeout can be overridden for a single operation
+        assert!(client
+            .with_timeout(Duration::from_secs(30), |fs| fs.list_dir(Path::new("/tmp")))
+            .is_ok());
+        assert_eq!(client.session.as_ref().unwrap().timeout(), 5000);
+        stalled.store(true, Ordering::Relaxed);
+        let err = client
+            .with_timeout(Duration::from_millis(500), |fs| fs.stat(Path::new("/tmp")))
+            .err()
+            .unwrap();
+        assert!(is_timeout(&err));
+        stalled.store(false, Ordering::Relaxed);
+        // the session is released after the timeout
+        assert!(!client.is_connected());
+        assert_eq!(
+            client.stat(Path::new("/tmp")).err().unwrap().kind,
+            RemoteErrorType::NotConnected
+        );
+        assert!(client.connect().is_ok());
+        assert!(client.stat(Path::new("/tmp")).is_ok());
+        assert!(client.disconnect().is_ok());
+    }
+
+    #[test]
+    fn should_not_ping_when_disconnected() {
+        let mut client = SftpFs::new(SshOpts::new("127.0.0.1"));
+        assert_eq!(
+            client.ping(Duration::from_secs(1)).err().unwrap().kind,
+            RemoteErrorType::NotConnected
+        );
+    }
+
+    #[test]
+    #[cfg(feature = "with-containers")]
+    #[serial]
+    fn should_ping_server_with_keepalive() {
+        crate::mock::logger();
+        let config_file = ssh_mock::create_ssh_config();
+        let mut client = SftpFs::new(
+            SshOpts::new("sftp")
+                .key_storage(Box::new(ssh_mock::MockSshKeyStorage::default()))
+                .config_file(config_file.path(), ParseRule::ALLOW_UNKNOWN_FIELDS)
+                .server_alive_interval(Duration::from_secs(1)),
+        );
+        assert!(client.connect().is_ok());
+        assert!(client.keepalive.is_some());
+        std::thread::sleep(Duration::from_secs(3));
+        assert!(client.is_connected());
+        assert!(client.ping(Duration::from_secs(5)).is_ok());
+        assert!(client.disconnect().is_ok());
+        assert!(client.keepalive.is_none());
+    }
+
+    #[test]
+    #[cfg(feature = "with-containers")]
+    #[serial]
+    fn should_get_connection_info() {
+        crate::mock::logger();
+        let config_file = ssh_mock::create_ssh_config();
+        let mut client = SftpFs::new(
+            SshOpts::new("sftp")
+                .key_storage(Box::new(ssh_mock::MockSshKeyStorage::default()))
+                .config_file(config_file.path(), ParseRule::ALLOW_UNKNOWN_FIELDS),
+        );
+        assert!(client.connection_info().is_none());
+        assert!(client.connect().is_ok());
+        let info = client.connection_info().unwrap();
+        let host_key = info.host_key.as_ref().unwrap();
+        assert!(host_key.sha256_fingerprint.starts_with("SHA256:"));
+        assert!(info.kex.is_some());
+        assert!(info.client_to_server.cipher.is_some());
+        assert!(info.server_to_client.mac.is_some());
+        assert_eq!(info.auth_methods, vec![crate::AuthMethod::PublicKey]);
+        assert!(client.disconnect().is_ok());
+        assert!(client.connection_info().is_none());
+    }
+
+    #[test]
+    fn should_not_initialize_sftp_filesystem_from_unauthenticated_session() {
+        let session = SshSession::new().unwrap();
+        assert_eq!(
+            SftpFs::from_session(session).err().unwrap().kind,
+            RemoteErrorType::AuthenticationFailed
+        );
+    }
+
+    #[test]
+    #[cfg(feature = "with-containers")]
+    #[serial]
+    fn should_share_session_with_scp_filesystem() {
+        use crate::ScpFs;
+
+        crate::mock::logger();
+        let config_file = ssh_mock::create_ssh_config();
+        let opts = SshOpts::new("sftp")
+            .key_storage(Box::new(ssh_mock::MockSshKeyStorage::default()))
+            .config_file(config_file.path(), ParseRule::ALLOW_UNKNOWN_FIELDS);
+        let session = commons::connect(&opts).unwrap();
+        let mut sftp = SftpFs::from_session(session.clone()).unwrap();
+        let mut scp = ScpFs::from_session(session).unwrap();
+        // sftp is opened lazily
+        assert!(sftp.sftp.is_none());
+        assert_eq!(sftp.pwd().unwrap(), scp.pwd().unwrap());
+        assert!(sftp.sftp.is_some());
+        assert!(sftp.list_dir(Path::new(".")).is_ok());
+        assert!(scp.list_dir(Path::new(".")).is_ok());
+        assert!(sftp.disconnect().is_ok());
+        assert!(sftp.connect().is_err());
+    }
+
     #[test]
     #[cfg(feature = "with-containers")]
     #[serial]
@@ -845,7 +1330,7 @@ mod test {
             .list_dir(wrkdir.as_path())
             .ok()
             .unwrap()
-            .get(0)
+            .first()
             .unwrap()
             .clone();
         assert_eq!(file.name().as_str(), "a.txt");
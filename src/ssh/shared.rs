@@ -0,0 +1,194 @@
+//! ## Shared
+//!
+//! ssh session shared between several clients
+
+use std::sync::Arc;
+use std::time::Duration;
+
+use remotefs::{RemoteError, RemoteErrorType, RemoteResult};
+use ssh2::Session;
+
+use super::keepalive::Keepalive;
+use super::{commons, ConnectionInfo, ScpFs, SftpFs, SshOpts};
+
+/// A ssh session which can be shared between several `SftpFs` and `ScpFs` clients, across threads,
+/// with a single authentication (like OpenSSH `ControlMaster`).
+///
+/// Each client has its own channel and working directory; calls to libssh2 are serialized on the session.
+/// Handles can be cloned and sent to other threads; the session is closed with `disconnect`
+/// or once every handle and client has been dropped.
+/// Keepalives, if enabled, are sent as long as a handle exists.
+#[derive(Clone)]
+pub struct SharedSession {
+    session: Session,
+    /// Keepalive of the session, shared by the handles
+    keepalive: Option<Arc<Keepalive>>,
+    /// Information about the connection; `None` if shared from a session
+    info: Option<Arc<ConnectionInfo>>,
+}
+
+impl SharedSession {
+    /// Connect and authenticate to the server with `opts`
+    pub fn connect(opts: &SshOpts) -> RemoteResult<Self> {
+        let connection = commons::establish(opts)?;
+        let mut shared = Self::from_session(connection.session)?;
+        shared.keepalive = connection.keepalive.map(Arc::new);
+        shared.info = Some(Arc::new(connection.info));
+        Ok(shared)
+    }
+
+    /// Share an authenticated session, which is then used in blocking mode
+    pub fn from_session(session: Session) -> RemoteResult<Self> {
+        if !session.authenticated() {
+            return Err(RemoteError::new_ex(
+                RemoteErrorType::AuthenticationFailed,
+                "session is not authenticated",
+            ));
+        }
+        session.set_blocking(true);
+        Ok(Self {
+            session,
+            keepalive: None,
+            info: None,
+        })
+    }
+
+    /// Create a new `SftpFs` client on the session, with its own SFTP channel
+    pub fn sftp_fs(&self) -> RemoteResult<SftpFs> {
+        SftpFs::from_session(self.session.clone())
+            .map(|client| client.with_connection_info(self.info.as_deref().cloned()))
+    }
+
+    /// Create a new `ScpFs` client on the session
+    pub fn scp_fs(&self) -> RemoteResult<ScpFs> {
+        ScpFs::from_session(self.session.clone())
+            .map(|client| client.with_connection_info(self.info.as_deref().cloned()))
+    }
+
+    /// Get a reference to the session
+    pub fn session(&self) -> &Session {
+        &self.session
+    }
+
+    /// Get the information about the connection (host key of the server, negotiated algorithms
+    /// and authentication methods); `None` if shared from a session
+    pub fn connection_info(&self) -> Option<&ConnectionInfo> {
+        self.info.as_deref()
+    }
+
+    /// Returns whether the session is authenticated and, if keepalives are enabled, still alive
+    pub fn is_connected(&self) -> bool {
+        self.session.authenticated()
+            && self
+                .keepalive
+                .as_ref()
+                .map(|x| x.is_alive())
+                .unwrap_or(true)
+    }
+
+    /// Check whether the server answers, waiting up to `timeout`
+    pub fn ping(&self, timeout: Duration) -> RemoteResult<()> {
+        commons::ping(&self.session, timeout)
+    }
+
+    /// Close the session, for every client sharing it
+    pub fn disconnect(&self) -> RemoteResult<()> {
+        debug!("Disconnecting shared session...");
+        self.session
+            .disconnect(None, "Mandi!", None)
+            .map_err(|err| RemoteError::new_ex(RemoteErrorType::ConnectionError, err))
+    }
+}
+
+#[cfg(test)]
+mod test {
+
+    #[cfg(feature = "with-containers")]
+    use std::io::Cursor;
+    #[cfg(feature = "with-containers")]
+    use std::path::{Path, PathBuf};
+
+    use pretty_assertions::assert_eq;
+    #[cfg(feature = "with-containers")]
+    use remotefs::fs::{Metadata, UnixPex};
+    #[cfg(feature = "with-containers")]
+    use remotefs::RemoteFs;
+    #[cfg(feature = "with-containers")]
+    use serial_test::serial;
+    #[cfg(feature = "with-containers")]
+    use ssh2_config::ParseRule;
+
+    use super::*;
+    #[cfg(feature = "with-containers")]
+    use crate::mock::ssh as ssh_mock;
+
+    #[test]
+    fn should_send_clients_across_threads() {
+        fn assert_send<T: Send>() {}
+        fn assert_sync<T: Sync>() {}
+        assert_send::<SftpFs>();
+        assert_send::<ScpFs>();
+        assert_send::<SharedSession>();
+        assert_sync::<SharedSession>();
+    }
+
+    #[test]
+    fn should_not_share_unauthenticated_session() {
+        assert_eq!(
+            SharedSession::from_session(Session::new().unwrap())
+                .err()
+                .unwrap()
+                .kind,
+            RemoteErrorType::AuthenticationFailed
+        );
+    }
+
+    #[test]
+    #[cfg(feature = "with-containers")]
+    #[serial]
+    fn should_upload_files_from_several_threads() {
+        crate::mock::logger();
+        let config_file = ssh_mock::create_ssh_config();
+        let opts = SshOpts::new("sftp")
+            .key_storage(Box::new(ssh_mock::MockSshKeyStorage::default()))
+            .config_file(config_file.path(), ParseRule::ALLOW_UNKNOWN_FIELDS);
+        let shared = SharedSession::connect(&opts).unwrap();
+        let tempdir = PathBuf::from("/tmp/shared_session_test");
+        let mut client = shared.sftp_fs().unwrap();
+        let _ = client.remove_dir_all(tempdir.as_path());
+        assert!(client
+            .create_dir(tempdir.as_path(), UnixPex::from(0o775))
+            .is_ok());
+        let workers: Vec<_> = (0..4)
+            .map(|i| {
+                let shared = shared.clone();
+                let tempdir = tempdir.clone();
+                std::thread::spawn(move || {
+                    let mut client = shared.sftp_fs().unwrap();
+                    assert!(client.change_dir(tempdir.as_path()).is_ok());
+                    let data = format!("file {i}\n");
+                    let reader = Cursor::new(data.clone().into_bytes());
+                    assert_eq!(
+                        client
+                            .create_file(
+                                Path::new(&format!("{i}.txt")),
+                                &Metadata::default().size(data.len() as u64),
+                                Box::new(reader),
+                            )
+                            .unwrap(),
+                        data.len() as u64
+                    );
+                    assert!(client.disconnect().is_ok());
+                })
+            })
+            .collect();
+        for worker in workers {
+            worker.join().unwrap();
+        }
+        // session is still open after the clients disconnected
+        assert!(shared.is_connected());
+        assert_eq!(client.list_dir(tempdir.as_path()).unwrap().len(), 4);
+        assert!(client.remove_dir_all(tempdir.as_path()).is_ok());
+        assert!(shared.disconnect().is_ok());
+    }
+}
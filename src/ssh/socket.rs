@@ -0,0 +1,191 @@
+//! ## Socket
+//!
+//! raw sockets ssh sessions run over
+
+use std::io;
+use std::time::Duration;
+
+use super::tunnel::SessionStream;
+
+/// Raw socket of a stream a ssh session runs over
+#[cfg(unix)]
+pub type RawSocket = std::os::unix::io::RawFd;
+
+/// Raw socket of a stream a ssh session runs over
+#[cfg(windows)]
+pub type RawSocket = std::os::windows::io::RawSocket;
+
+/// Get the raw socket of `stream`
+#[cfg(unix)]
+pub fn raw_socket<S: SessionStream + ?Sized>(stream: &S) -> RawSocket {
+    stream.as_raw_fd()
+}
+
+/// Get the raw socket of `stream`
+#[cfg(windows)]
+pub fn raw_socket<S: SessionStream + ?Sized>(stream: &S) -> RawSocket {
+    stream.as_raw_socket()
+}
+
+/// Directions a socket is waited for, or is ready for
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct Interest {
+    pub readable: bool,
+    pub writable: bool,
+}
+
+/// Wait until any of `sockets` is ready for the directions it is waited for, up to `timeout`.
+///
+/// Returns the directions each socket is ready for; a closed or failed socket is ready for both
+#[cfg(unix)]
+pub fn wait(sockets: &[(RawSocket, Interest)], timeout: Duration) -> io::Result<Vec<Interest>> {
+    let mut fds: Vec<libc::pollfd> = sockets
+        .iter()
+        .map(|(socket, interest)| libc::pollfd {
+            fd: *socket,
+            events: match (interest.readable, interest.writable) {
+                (true, true) => libc::POLLIN | libc::POLLOUT,
+                (true, false) => libc::POLLIN,
+                (false, true) => libc::POLLOUT,
+                (false, false) => 0,
+            },
+            revents: 0,
+        })
+        .collect();
+    let timeout = timeout.as_millis().min(libc::c_int::MAX as u128) as libc::c_int;
+    // SAFETY: `fds` holds `fds.len()` poll descriptors
+    if unsafe { libc::poll(fds.as_mut_ptr(), fds.len() as libc::nfds_t, timeout) } == -1 {
+        let err = io::Error::last_os_error();
+        return match err.kind() {
+            io::ErrorKind::Interrupted => Ok(vec![Interest::default(); sockets.len()]),
+            _ => Err(err),
+        };
+    }
+    let failed = libc::POLLHUP | libc::POLLERR | libc::POLLNVAL;
+    Ok(fds
+        .iter()
+        .map(|fd| Interest {
+            readable: fd.revents & (libc::POLLIN | failed) != 0,
+            writable: fd.revents & (libc::POLLOUT | failed) != 0,
+        })
+        .collect())
+}
+
+/// Wait until any of `sockets` is ready for the directions it is waited for, up to `timeout`.
+///
+/// Returns the directions each socket is ready for; a closed or failed socket is ready for both
+#[cfg(windows)]
+pub fn wait(sockets: &[(RawSocket, Interest)], timeout: Duration) -> io::Result<Vec<Interest>> {
+    use windows_sys::Win32::Networking::WinSock::{
+        WSAGetLastError, WSAPoll, POLLERR, POLLHUP, POLLNVAL, POLLRDNORM, POLLWRNORM, SOCKET_ERROR,
+        WSAPOLLFD,
+    };
+
+    let mut fds: Vec<WSAPOLLFD> = sockets
+        .iter()
+        .map(|(socket, interest)| WSAPOLLFD {
+            fd: *socket as usize,
+            events: match (interest.readable, interest.writable) {
+                (true, true) => POLLRDNORM | POLLWRNORM,
+                (true, false) => POLLRDNORM,
+                (false, true) => POLLWRNORM,
+                (false, false) => 0,
+            },
+            revents: 0,
+        })
+        .collect();
+    let timeout = timeout.as_millis().min(i32::MAX as u128) as i32;
+    // SAFETY: `fds` holds `fds.len()` poll descriptors
+    if unsafe { WSAPoll(fds.as_mut_ptr(), fds.len() as u32, timeout) } == SOCKET_ERROR {
+        return Err(io::Error::from_raw_os_error(unsafe { WSAGetLastError() }));
+    }
+    let failed = POLLHUP | POLLERR | POLLNVAL;
+    Ok(fds
+        .iter()
+        .map(|fd| Interest {
+            readable: fd.revents & (POLLRDNORM | failed) != 0,
+            writable: fd.revents & (POLLWRNORM | failed) != 0,
+        })
+        .collect())
+}
+
+/// Get the amount of bytes received on `socket` and not read yet
+#[cfg(unix)]
+pub fn pending(socket: RawSocket) -> io::Result<usize> {
+    let mut pending: libc::c_int = 0;
+    // SAFETY: FIONREAD writes an int to `pending`
+    match unsafe { libc::ioctl(socket, libc::FIONREAD, &mut pending) } {
+        -1 => Err(io::Error::last_os_error()),
+        _ => Ok(pending.max(0) as usize),
+    }
+}
+
+/// Get the amount of bytes received on `socket` and not read yet
+#[cfg(windows)]
+pub fn pending(socket: RawSocket) -> io::Result<usize> {
+    use windows_sys::Win32::Networking::WinSock::{ioctlsocket, WSAGetLastError, FIONREAD};
+
+    let mut pending: u32 = 0;
+    // SAFETY: FIONREAD writes an u32 to `pending`
+    match unsafe { ioctlsocket(socket as usize, FIONREAD, &mut pending) } {
+        0 => Ok(pending as usize),
+        _ => Err(io::Error::from_raw_os_error(unsafe { WSAGetLastError() })),
+    }
+}
+
+#[cfg(test)]
+mod test {
+
+    use std::io::Write;
+
+    use pretty_assertions::assert_eq;
+
+    use super::*;
+    use crate::ssh::tunnel::socket_pair;
+
+    #[test]
+    fn should_wait_until_socket_is_ready() {
+        let (mut local, remote) = socket_pair().unwrap();
+        let socket = raw_socket(&remote);
+        let both = Interest {
+            readable: true,
+            writable: true,
+        };
+        // nothing to read yet
+        assert_eq!(
+            wait(&[(socket, both)], Duration::from_secs(5)).unwrap(),
+            vec![Interest {
+                readable: false,
+                writable: true,
+            }]
+        );
+        let readable = Interest {
+            readable: true,
+            writable: false,
+        };
+        assert_eq!(
+            wait(&[(socket, readable)], Duration::from_millis(10)).unwrap(),
+            vec![Interest::default()]
+        );
+        local.write_all(b"hello").unwrap();
+        assert_eq!(
+            wait(&[(socket, readable)], Duration::from_secs(5)).unwrap(),
+            vec![readable]
+        );
+    }
+
+    #[test]
+    fn should_get_pending_bytes() {
+        let (mut local, remote) = socket_pair().unwrap();
+        let socket = raw_socket(&remote);
+        assert_eq!(pending(socket).unwrap(), 0);
+        local.write_all(b"hello").unwrap();
+        // local sockets deliver data immediately, but windows has no local sockets
+        let mut attempts = 0;
+        while pending(socket).unwrap() < 5 && attempts < 100 {
+            std::thread::sleep(std::time::Duration::from_millis(10));
+            attempts += 1;
+        }
+        assert_eq!(pending(socket).unwrap(), 5);
+    }
+}
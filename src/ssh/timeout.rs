@@ -0,0 +1,130 @@
+//! ## Timeout
+//!
+//! timeout of the operations on a session
+
+use std::time::Duration;
+
+use remotefs::{RemoteError, RemoteErrorType};
+use ssh2::{ErrorCode, Session};
+
+/// Message of the error returned by an operation which didn't complete within the operation timeout
+pub const TIMED_OUT: &str = "operation timed out";
+
+// libssh2 error codes of timeouts
+const LIBSSH2_ERROR_TIMEOUT: i32 = -9;
+const LIBSSH2_ERROR_SOCKET_TIMEOUT: i32 = -30;
+
+/// Returns whether `err` has been returned by an operation which didn't complete within the operation timeout.
+///
+/// The server may still be processing the operation
+pub fn is_timeout(err: &RemoteError) -> bool {
+    err.kind == RemoteErrorType::ConnectionError
+        && err
+            .msg
+            .as_deref()
+            .map(|x| x.starts_with(TIMED_OUT))
+            .unwrap_or(false)
+}
+
+/// Make the timeout error of the operation which failed with `err`, if the last error of `session` is a timeout
+pub(crate) fn timed_out(session: Option<&Session>, err: RemoteError) -> RemoteError {
+    let timed_out = session
+        .and_then(ssh2::Error::last_session_error)
+        .map(|x| is_timeout_error(&x))
+        .unwrap_or(false);
+    if timed_out && !is_timeout(&err) {
+        error!("Operation timed out: {err}");
+        RemoteError::new_ex(
+            RemoteErrorType::ConnectionError,
+            format!("{TIMED_OUT}: {err}"),
+        )
+    } else {
+        err
+    }
+}
+
+/// Set the timeout of the blocking calls on `session`; zero disables it
+pub(crate) fn set_timeout(session: &Session, timeout: Duration) {
+    session.set_timeout(timeout.as_millis().min(u32::MAX as u128) as u32);
+}
+
+/// Overrides the timeout of a session, until dropped
+pub(crate) struct TimeoutGuard {
+    session: Session,
+    previous_timeout: u32,
+}
+
+impl TimeoutGuard {
+    /// Set the timeout of `session` to `timeout`; the previous timeout is restored when the guard is dropped
+    pub fn new(session: Session, timeout: Duration) -> Self {
+        let previous_timeout = session.timeout();
+        set_timeout(&session, timeout);
+        Self {
+            session,
+            previous_timeout,
+        }
+    }
+}
+
+impl Drop for TimeoutGuard {
+    fn drop(&mut self) {
+        self.session.set_timeout(self.previous_timeout);
+    }
+}
+
+/// Returns whether `err` is a timeout of libssh2
+fn is_timeout_error(err: &ssh2::Error) -> bool {
+    matches!(
+        err.code(),
+        ErrorCode::Session(LIBSSH2_ERROR_TIMEOUT | LIBSSH2_ERROR_SOCKET_TIMEOUT)
+    )
+}
+
+#[cfg(test)]
+mod test {
+
+    use pretty_assertions::assert_eq;
+
+    use super::*;
+
+    #[test]
+    fn should_tell_whether_operation_timed_out() {
+        let err = RemoteError::new_ex(
+            RemoteErrorType::ConnectionError,
+            format!("{TIMED_OUT}: could not read directory"),
+        );
+        assert!(is_timeout(&err));
+        assert!(!is_timeout(&RemoteError::new(
+            RemoteErrorType::ConnectionError
+        )));
+        assert!(!is_timeout(&RemoteError::new_ex(
+            RemoteErrorType::ProtocolError,
+            TIMED_OUT
+        )));
+        // not a timeout of the session
+        let err = timed_out(
+            Some(&Session::new().unwrap()),
+            RemoteError::new(RemoteErrorType::StatFailed),
+        );
+        assert_eq!(err.kind, RemoteErrorType::StatFailed);
+        assert!(is_timeout_error(&ssh2::Error::new(
+            ErrorCode::Session(LIBSSH2_ERROR_TIMEOUT),
+            "Timed out waiting on socket"
+        )));
+        assert!(!is_timeout_error(&ssh2::Error::new(
+            ErrorCode::Session(-43),
+            "Failed getting banner"
+        )));
+    }
+
+    #[test]
+    fn should_restore_session_timeout() {
+        let session = Session::new().unwrap();
+        set_timeout(&session, Duration::from_secs(10));
+        {
+            let _guard = TimeoutGuard::new(session.clone(), Duration::from_millis(500));
+            assert_eq!(session.timeout(), 500);
+        }
+        assert_eq!(session.timeout(), 10_000);
+    }
+}
@@ -0,0 +1,449 @@
+//! ## Tunnel
+//!
+//! streams to run ssh sessions over something else than a direct tcp connection
+
+use std::io::{self, BufRead, BufReader, Read, Write};
+use std::net::Shutdown;
+use std::process::{Command, Stdio};
+use std::thread;
+use std::time::Duration;
+
+use ssh2::{BlockDirections, Channel, Session};
+
+use super::keepalive::Keepalive;
+use super::socket::{self, Interest, RawSocket};
+
+/// A stream a ssh session can run over.
+///
+/// Implemented by every type with a raw file descriptor (on windows, a raw socket), such as `TcpStream` and `UnixStream`
+#[cfg(unix)]
+pub trait SessionStream: std::os::unix::io::AsRawFd + Send + 'static {}
+
+#[cfg(unix)]
+impl<T: std::os::unix::io::AsRawFd + Send + 'static> SessionStream for T {}
+
+/// A stream a ssh session can run over.
+///
+/// Implemented by every type with a raw file descriptor (on windows, a raw socket), such as `TcpStream` and `UnixStream`
+#[cfg(windows)]
+pub trait SessionStream: std::os::windows::io::AsRawSocket + Send + 'static {}
+
+#[cfg(windows)]
+impl<T: std::os::windows::io::AsRawSocket + Send + 'static> SessionStream for T {}
+
+/// Local end of a tunnel
+#[cfg(unix)]
+pub type LocalStream = std::os::unix::net::UnixStream;
+
+/// Local end of a tunnel
+#[cfg(windows)]
+pub type LocalStream = std::net::TcpStream;
+
+/// Size of the buffers used to move data through tunnels
+const BUFFER_SIZE: usize = 32 * 1024;
+/// Longest time to wait for the ends of a tunnel to be ready, before trying to move data again
+const IDLE_WAIT: Duration = Duration::from_secs(1);
+
+/// Create a pair of connected local streams
+#[cfg(unix)]
+pub fn socket_pair() -> io::Result<(LocalStream, LocalStream)> {
+    LocalStream::pair()
+}
+
+/// Create a pair of connected local streams
+#[cfg(windows)]
+pub fn socket_pair() -> io::Result<(LocalStream, LocalStream)> {
+    use std::net::{Ipv4Addr, TcpListener};
+
+    let listener = TcpListener::bind((Ipv4Addr::LOCALHOST, 0))?;
+    let local = LocalStream::connect(listener.local_addr()?)?;
+    loop {
+        let (remote, addr) = listener.accept()?;
+        // make sure nobody else connected to the listener
+        if addr == local.local_addr()? {
+            return Ok((local, remote));
+        }
+    }
+}
+
+/// Open a `direct-tcpip` channel to `host:port` on `session`, which runs over `socket`, and return a local stream connected to it.
+///
+/// The session and its keepalive are moved to a thread which moves data between the channel and the stream,
+/// until either of them is closed or the keepalives of the session fail
+pub fn direct_tcpip(
+    session: Session,
+    socket: RawSocket,
+    keepalive: Option<Keepalive>,
+    host: &str,
+    port: u16,
+) -> io::Result<LocalStream> {
+    let channel = session
+        .channel_direct_tcpip(host, port, None)
+        .map_err(io::Error::from)?;
+    let (local, remote) = socket_pair()?;
+    remote.set_nonblocking(true)?;
+    session.set_blocking(false);
+    let target = format!("{host}:{port}");
+    let channel = ChannelStream {
+        channel,
+        session,
+        socket,
+        keepalive,
+        read_blocked: Interest::default(),
+        write_blocked: Interest::default(),
+    };
+    thread::Builder::new()
+        .name(format!("ssh-tunnel-{target}"))
+        .spawn(move || {
+            // the session is kept alive as long as the channel
+            pump(remote, channel);
+            debug!("Tunnel to {target} closed");
+        })?;
+    Ok(local)
+}
+
+/// Spawn `command` with the system shell and return a local stream connected to its stdin and stdout.
+///
+/// The command is killed once the local stream is closed; its stderr is logged
+pub fn proxy_command(command: &str) -> io::Result<LocalStream> {
+    let mut child = shell(command)
+        .stdin(Stdio::piped())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        .spawn()?;
+    let (local, remote) = socket_pair()?;
+    let mut stdin = child.stdin.take().expect("stdin is piped");
+    let mut stdout = child.stdout.take().expect("stdout is piped");
+    let stderr = child.stderr.take().expect("stderr is piped");
+    let mut remote_reader = remote.try_clone()?;
+    let mut remote_writer = remote;
+    let to_command = thread::Builder::new()
+        .name("ssh-proxy-command-stdin".to_string())
+        .spawn(move || {
+            let _ = copy(&mut remote_reader, &mut stdin);
+        })?;
+    let from_command = thread::Builder::new()
+        .name("ssh-proxy-command-stdout".to_string())
+        .spawn(move || {
+            let _ = copy(&mut stdout, &mut remote_writer);
+            // let both the session and the stdin thread know the command is gone
+            let _ = remote_writer.shutdown(Shutdown::Both);
+        })?;
+    thread::Builder::new()
+        .name("ssh-proxy-command-stderr".to_string())
+        .spawn(move || {
+            for line in BufReader::new(stderr).lines().map_while(Result::ok) {
+                debug!("ProxyCommand: {line}");
+            }
+        })?;
+    let command = command.to_string();
+    thread::Builder::new()
+        .name("ssh-proxy-command".to_string())
+        .spawn(move || {
+            // the stdin thread terminates once the local stream has been closed
+            let _ = to_command.join();
+            let _ = child.kill();
+            match child.wait() {
+                Ok(status) => debug!("ProxyCommand '{command}' exited with {status}"),
+                Err(err) => error!("Could not wait ProxyCommand '{command}': {err}"),
+            }
+            let _ = from_command.join();
+        })?;
+    Ok(local)
+}
+
+/// Copy data from `reader` to `writer` until `reader` is closed.
+///
+/// Unlike `io::copy`, which may splice pipes and sockets in the kernel, data is forwarded as soon as it is read
+fn copy<R: Read, W: Write>(reader: &mut R, writer: &mut W) -> io::Result<()> {
+    let mut buffer = vec![0; BUFFER_SIZE];
+    loop {
+        let n = reader.read(&mut buffer)?;
+        if n == 0 {
+            return Ok(());
+        }
+        writer.write_all(&buffer[..n])?;
+        writer.flush()?;
+    }
+}
+
+/// Build a command to run `command` with the system shell
+#[cfg(unix)]
+fn shell(command: &str) -> Command {
+    let mut shell = Command::new("sh");
+    // exec, so that the proxy is the process which gets killed
+    shell.arg("-c").arg(format!("exec {command}"));
+    shell
+}
+
+/// Build a command to run `command` with the system shell
+#[cfg(windows)]
+fn shell(command: &str) -> Command {
+    let mut shell = Command::new("cmd");
+    shell.arg("/C").arg(command);
+    shell
+}
+
+/// End of a tunnel: a non-blocking stream, which tells the socket to wait on once reading or writing would block
+pub trait TunnelEnd: Read + Write {
+    /// Get the socket of the end and the directions to wait for, to read from the end if `read` and to write to it if `write`
+    fn interest(&self, read: bool, write: bool) -> (RawSocket, Interest);
+}
+
+impl TunnelEnd for LocalStream {
+    fn interest(&self, read: bool, write: bool) -> (RawSocket, Interest) {
+        let interest = Interest {
+            readable: read,
+            writable: write,
+        };
+        (socket::raw_socket(self), interest)
+    }
+}
+
+/// Channel wrapper which reports the end of the stream only once the remote sends EOF
+struct ChannelStream {
+    channel: Channel,
+    /// Non-blocking session of the channel
+    session: Session,
+    /// Socket the session runs over
+    socket: RawSocket,
+    keepalive: Option<Keepalive>,
+    /// Directions the session socket must be ready for, once the last read would block
+    read_blocked: Interest,
+    /// Directions the session socket must be ready for, once the last write would block
+    write_blocked: Interest,
+}
+
+impl ChannelStream {
+    /// Returns an error if the keepalives of the session failed
+    fn check_alive(&self) -> io::Result<()> {
+        match self.keepalive.as_ref() {
+            Some(keepalive) if !keepalive.is_alive() => Err(io::Error::new(
+                io::ErrorKind::ConnectionAborted,
+                "server didn't answer to keepalives",
+            )),
+            _ => Ok(()),
+        }
+    }
+
+    /// Get the directions the session socket must be ready for, to go on with the last operation which would block
+    fn blocked(&self) -> Interest {
+        let directions = self.session.block_directions();
+        Interest {
+            readable: matches!(directions, BlockDirections::Inbound | BlockDirections::Both),
+            writable: matches!(
+                directions,
+                BlockDirections::Outbound | BlockDirections::Both
+            ),
+        }
+    }
+}
+
+impl Read for ChannelStream {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        self.check_alive()?;
+        if let Some(keepalive) = self.keepalive.as_ref() {
+            // data from the server, including the answers to keepalives, is read along with the channel data
+            if socket::pending(self.socket).unwrap_or_default() > 0 {
+                keepalive.received();
+            }
+        }
+        self.read_blocked = Interest::default();
+        let result = match self.channel.read(buf) {
+            Ok(0) if !self.channel.eof() => Err(io::ErrorKind::WouldBlock.into()),
+            res => res,
+        };
+        if matches!(&result, Err(err) if err.kind() == io::ErrorKind::WouldBlock) {
+            self.read_blocked = self.blocked();
+        }
+        result
+    }
+}
+
+impl Write for ChannelStream {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.check_alive()?;
+        self.write_blocked = Interest::default();
+        let result = self.channel.write(buf);
+        if matches!(&result, Err(err) if err.kind() == io::ErrorKind::WouldBlock) {
+            self.write_blocked = self.blocked();
+        }
+        result
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.channel.flush()
+    }
+}
+
+impl TunnelEnd for ChannelStream {
+    fn interest(&self, read: bool, write: bool) -> (RawSocket, Interest) {
+        // only the operations which are going to be retried matter
+        let blocked = |wanted: bool, blocked: Interest| match wanted {
+            true => blocked,
+            false => Interest::default(),
+        };
+        let read_blocked = blocked(read, self.read_blocked);
+        let write_blocked = blocked(write, self.write_blocked);
+        // the channel data arrives on the session socket; a pending write may wait for the server to widen the window
+        let interest = Interest {
+            readable: read || write_blocked.readable,
+            writable: read_blocked.writable || write_blocked.writable,
+        };
+        (self.socket, interest)
+    }
+}
+
+/// Move data between the non-blocking streams `a` and `b`, until either of them is closed or fails.
+///
+/// Once no data can be moved, waits for the sockets of the streams to be ready, so that idle tunnels don't keep the cpu busy
+pub fn pump<A: TunnelEnd, B: TunnelEnd>(mut a: A, mut b: B) {
+    let mut a_to_b = Pipe::default();
+    let mut b_to_a = Pipe::default();
+    loop {
+        let moved = match a_to_b
+            .transfer(&mut a, &mut b)
+            .and_then(|a_to_b| Ok(b_to_a.transfer(&mut b, &mut a)? || a_to_b))
+        {
+            Ok(moved) => moved,
+            Err(err) => {
+                trace!("Tunnel stopped: {err}");
+                break;
+            }
+        };
+        if moved {
+            continue;
+        }
+        // read from a stream only if the data read before has been written to the other one
+        let sockets = [
+            a.interest(a_to_b.is_empty(), !b_to_a.is_empty()),
+            b.interest(b_to_a.is_empty(), !a_to_b.is_empty()),
+        ];
+        if let Err(err) = socket::wait(&sockets, IDLE_WAIT) {
+            error!("Tunnel stopped: could not wait for the streams: {err}");
+            break;
+        }
+    }
+}
+
+/// Data read from a stream and not written yet to the other one
+struct Pipe {
+    buffer: Vec<u8>,
+    pending: usize,
+    written: usize,
+}
+
+impl Default for Pipe {
+    fn default() -> Self {
+        Self {
+            buffer: vec![0; BUFFER_SIZE],
+            pending: 0,
+            written: 0,
+        }
+    }
+}
+
+impl Pipe {
+    /// Returns whether all the data read has been written
+    fn is_empty(&self) -> bool {
+        self.written == self.pending
+    }
+
+    /// Move data from `reader` to `writer`; returns whether any data was moved.
+    ///
+    /// Returns an error if `reader` has been closed or if either of the streams failed
+    fn transfer<R: Read, W: Write>(&mut self, reader: &mut R, writer: &mut W) -> io::Result<bool> {
+        let mut moved = false;
+        if self.is_empty() {
+            match reader.read(&mut self.buffer) {
+                Ok(0) => return Err(io::ErrorKind::UnexpectedEof.into()),
+                Ok(n) => {
+                    self.pending = n;
+                    self.written = 0;
+                    moved = true;
+                }
+                Err(err) if err.kind() == io::ErrorKind::WouldBlock => return Ok(false),
+                Err(err) => return Err(err),
+            }
+        }
+        while self.written < self.pending {
+            match writer.write(&self.buffer[self.written..self.pending]) {
+                Ok(0) => return Err(io::ErrorKind::WriteZero.into()),
+                Ok(n) => {
+                    self.written += n;
+                    moved = true;
+                }
+                Err(err) if err.kind() == io::ErrorKind::WouldBlock => break,
+                Err(err) => return Err(err),
+            }
+        }
+        Ok(moved)
+    }
+}
+
+#[cfg(test)]
+mod test {
+
+    use pretty_assertions::assert_eq;
+
+    use super::*;
+
+    #[test]
+    #[cfg(unix)]
+    fn should_move_data_through_proxy_command() {
+        let mut stream = proxy_command("cat").unwrap();
+        stream.write_all(b"SSH-2.0-remotefs\r\n").unwrap();
+        let mut buf = [0; 18];
+        stream.read_exact(&mut buf).unwrap();
+        assert_eq!(&buf, b"SSH-2.0-remotefs\r\n");
+    }
+
+    #[test]
+    #[cfg(unix)]
+    fn should_close_stream_when_proxy_command_exits() {
+        let mut stream = proxy_command("echo hello").unwrap();
+        let mut buf = String::new();
+        stream.read_to_string(&mut buf).unwrap();
+        assert_eq!(buf.as_str(), "hello\n");
+    }
+
+    #[test]
+    fn should_move_data_through_tunnel() {
+        let (mut client, a) = socket_pair().unwrap();
+        let (b, mut server) = socket_pair().unwrap();
+        a.set_nonblocking(true).unwrap();
+        b.set_nonblocking(true).unwrap();
+        let handle = thread::spawn(move || pump(a, b));
+        client.write_all(b"hello").unwrap();
+        let mut buf = [0; 5];
+        server.read_exact(&mut buf).unwrap();
+        assert_eq!(&buf, b"hello");
+        // large payload in the other direction
+        let payload: Vec<u8> = (0..BUFFER_SIZE * 4).map(|x| x as u8).collect();
+        let writer = {
+            let payload = payload.clone();
+            thread::spawn(move || server.write_all(&payload).unwrap())
+        };
+        let mut received = vec![0; payload.len()];
+        client.read_exact(&mut received).unwrap();
+        assert_eq!(received, payload);
+        writer.join().unwrap();
+        // closing a side stops the tunnel
+        drop(client);
+        handle.join().unwrap();
+    }
+
+    #[test]
+    fn should_wait_for_local_stream_directions() {
+        let (local, _) = socket_pair().unwrap();
+        let (socket, interest) = local.interest(true, false);
+        assert_eq!(socket, socket::raw_socket(&local));
+        assert_eq!(
+            interest,
+            Interest {
+                readable: true,
+                writable: false,
+            }
+        );
+    }
+}